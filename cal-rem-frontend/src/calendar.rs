@@ -1,33 +1,55 @@
 use seed::{prelude::*, *};
-use chrono::{Datelike, Weekday};
-use cal_rem_shared::{Entry, Month};
+use chrono::{Datelike, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use cal_rem_shared::{Entry, Month, month_name, month_to_num, num_to_month};
 use crate::Msg;
 
-pub fn future_calendar_nodes_from_entries(entries: &Vec<Entry>) -> Vec<Node<Msg>> {
+pub fn future_calendar_nodes_from_entries(entries: &Vec<Entry>, selected_tag: &Option<String>, time_zone: Tz) -> Vec<Node<Msg>> {
     let now = (js_sys::Date::now() / 1000.0) as i64;
     let mut month: Option<Month> = None;
-    let is_in_the_future = |entry: &&Entry| now - 3600 * 4 < entry.get_oslo_date_time().timestamp();
+    let is_in_the_future = |entry: &&Entry| entry.get_end_date_time(time_zone).map_or(false, |end| now - 3600 * 4 < end.timestamp());
+    let has_selected_tag = |entry: &&Entry| selected_tag.as_ref().map_or(true, |tag| entry.tags.contains(tag));
     
-    entries.iter().filter(is_in_the_future).flat_map(|entry| {
+    entries.iter().filter(is_in_the_future).filter(has_selected_tag).flat_map(|entry| {
         let mut els = vec![];
         month_header(&entry, &mut month).map(|month_header| els.push(month_header));
-        els.push(calendar_entry(&entry));
+        els.push(calendar_entry(&entry, time_zone));
         els
     }).collect()
 }
 
-fn calendar_entry(entry: &Entry) -> Node<Msg> { 
+pub fn tag_filter(selected_tag: &Option<String>) -> Option<Node<Msg>> {
+    selected_tag.as_ref().map(|tag| {
+        div![
+            style!{St::MarginTop => px(8)},
+            tag_label(tag),
+            span![
+                "vis alle",
+                style!{St::FontSize => px(12)},
+                style!{St::Cursor => "pointer"},
+                style!{St::TextDecoration => "underline"},
+                ev(Ev::Click, |_| Msg::SelectTag(None)),
+            ]
+        ]
+    })
+}
+
+fn calendar_entry(entry: &Entry, time_zone: Tz) -> Node<Msg> { 
     div![
-        day_date(entry),
+        day_date(entry, time_zone),
         style!{St::Display => "flex"},
         style!{St::AlignItems => "baseline"},
         style!{St::MarginBottom => px(2)},
         div![
             style!{St::Display => "flex"},
             style!{St::FlexDirection => "column"},
+            entry.tags.first().map(|tag| style!{St::BorderLeft => format!("4px solid {}", tag_colour(tag))}),
+            style!{St::PaddingLeft => px(8)},
             span![
                 entry.description.clone(),
                 style!{St::FontSize => px(24)},
+                IF!(entry.uncertain_date => style!{St::FontStyle => "italic"}),
+                entry.tags.iter().map(|tag| tag_label(tag)),
             ],
             span![
                 location(entry),
@@ -42,11 +64,11 @@ fn calendar_entry(entry: &Entry) -> Node<Msg> {
     ]
 }
 
-fn day_date(entry: &Entry) -> Vec<Node<Msg>> {
+fn day_date(entry: &Entry, time_zone: Tz) -> Vec<Node<Msg>> {
     if entry.start_date.is_some() && entry.end_date.is_none() {
         vec![
             span![
-                short_day_name(&entry.get_oslo_date_time().weekday()),
+                entry.get_date_time(time_zone).map_or("", |date_time| short_day_name(&date_time.weekday())),
                 style!{St::Flex => "0 0 24px"},
                 style!{St::FontSize => px(12)},
             ],
@@ -63,7 +85,7 @@ fn day_date(entry: &Entry) -> Vec<Node<Msg>> {
             span![
                 date(&entry),
                 style!{St::Flex => "0 0 60px"},
-                style!{St::FontSize => px(24)},
+                style!{St::FontSize => px(if spans_months(entry) { 16 } else { 24 })},
                 style!{St::PaddingRight => px(24)},
                 style!{St::TextAlign => "right"},
             ]
@@ -83,29 +105,67 @@ fn month_header(entry: &Entry, current_month: &mut Option<Month>) -> Option<Node
     }
 }
 
-pub fn todays_date_description() -> String {
-    let date = js_sys::Date::new(&JsValue::from_f64(js_sys::Date::now()));
-    format!("{} {}. {}", short_day_name_from_js_day(date.get_utc_day()), date.get_utc_date(), month_name_from_js_month(date.get_utc_month()))
+pub fn todays_date_description(time_zone: Tz) -> String {
+    let date = Utc.timestamp_millis(js_sys::Date::now() as i64).with_timezone(&time_zone);
+    format!("{} {}. {}", short_day_name(&date.weekday()), date.day(), month_name(num_to_month(date.month()).unwrap()))
 }
 
 fn date(entry: &Entry) -> String {
+    let uncertain = if entry.uncertain_date { "?" } else { "" };
+
     if entry.start_date.is_some() {
         if entry.end_date.is_some() {
-            format!("{}-{}", entry.start_date.unwrap().to_string(), entry.end_date.unwrap().to_string())
+            if !spans_months(entry) {
+                format!("{}-{}{}", entry.start_date.unwrap().to_string(), entry.end_date.unwrap().to_string(), uncertain)
+            } else {
+                // ranges into another month show the month number on both ends, like 28.12-3.1
+                format!("{}.{}-{}.{}{}",
+                    entry.start_date.unwrap().to_string(), month_to_num(entry.month),
+                    entry.end_date.unwrap().to_string(), month_to_num(entry.end_month.unwrap_or(entry.month)), uncertain)
+            }
         } else {
-            format!("{}", entry.start_date.unwrap().to_string())
+            format!("{}{}", entry.start_date.unwrap().to_string(), uncertain)
         }
     } else {
         "".to_string()
     }
 }
 
+fn tag_label(tag: &str) -> Node<Msg> {
+    let selected_tag = tag.to_string();
+
+    span![
+        format!("#{}", tag),
+        style!{St::FontSize => px(12)},
+        style!{St::FontStyle => "normal"},
+        style!{St::Color => "#fff"},
+        style!{St::Background => tag_colour(tag)},
+        style!{St::Padding => px(2) + " " + &px(6)},
+        style!{St::MarginLeft => px(8)},
+        style!{St::MarginRight => px(8)},
+        style!{St::Cursor => "pointer"},
+        ev(Ev::Click, move |_| Msg::SelectTag(Some(selected_tag))),
+    ]
+}
+
+// the same tag always gets the same colour, without having to configure one per tag.
+fn tag_colour(tag: &str) -> String {
+    let hue = tag.bytes().fold(0u32, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as u32)) % 360;
+    format!("hsl({}, 40%, 55%)", hue)
+}
+
+fn spans_months(entry: &Entry) -> bool {
+    entry.end_date.is_some() && (entry.end_month.unwrap_or(entry.month) != entry.month || entry.end_year.unwrap_or(entry.year) != entry.year)
+}
+
 fn time(entry: &Entry) -> String {
+    let time_zone = entry.time_zone.as_ref().map_or("".to_string(), |time_zone| format!(" ({})", time_zone));
+
     if entry.start_time.is_some() {
         if entry.end_time.is_some() {
-            format!("{:02}.{:02}-{:02}.{:02}", entry.start_time.unwrap().hour, entry.start_time.unwrap().minute, entry.end_time.unwrap().hour,  entry.end_time.unwrap().minute)
+            format!("{:02}.{:02}-{:02}.{:02}{}", entry.start_time.unwrap().hour, entry.start_time.unwrap().minute, entry.end_time.unwrap().hour,  entry.end_time.unwrap().minute, time_zone)
         } else {
-            format!("{:02}.{:02}", entry.start_time.unwrap().hour, entry.start_time.unwrap().minute)
+            format!("{:02}.{:02}{}", entry.start_time.unwrap().hour, entry.start_time.unwrap().minute, time_zone)
         }
     } else {
         "".to_string()
@@ -120,24 +180,7 @@ fn location(entry: &Entry) -> String {
     }
 }
 
-fn month_name(month: Month) -> &'static str {
-    match month {
-        Month::January => "Januar",
-        Month::February => "Februar",
-        Month::March => "Mars",
-        Month::April => "April",
-        Month::May => "Mai",
-        Month::June => "Juni",
-        Month::July => "Juli",
-        Month::August => "August",
-        Month::September => "September",
-        Month::October => "Oktober",
-        Month::November => "November",
-        Month::December => "Desember",
-    }
-}
-
-fn short_day_name(weekday: &Weekday) -> &str {
+fn short_day_name(weekday: &Weekday) -> &'static str {
     match weekday {
         Weekday::Mon => "man",
         Weekday::Tue => "tir",
@@ -148,34 +191,3 @@ fn short_day_name(weekday: &Weekday) -> &str {
         Weekday::Sun => "søn",
     }
 }
-
-fn month_name_from_js_month(month: u32) -> &'static str {
-    match month {
-        0 => "Januar",
-        1 => "Februar",
-        2 => "Mars",
-        3 => "April",
-        4 => "Mai",
-        5 => "Juni",
-        6 => "Juli",
-        7 => "August",
-        8 => "September",
-        9 => "Oktober",
-        10 => "November",
-        11 => "Desember",
-        _ => "error",
-    }
-}
-
-fn short_day_name_from_js_day(weekday: u32) -> &'static str {
-    match weekday {
-        1 => "man",
-        2 => "tir",
-        3 => "ons",
-        4 => "tor",
-        5 => "fre",
-        6 => "lør",
-        0 => "søn",
-        _ => "error",
-    }
-}
\ No newline at end of file
@@ -4,34 +4,58 @@ mod calendar;
 mod todo;
 
 use seed::{prelude::*, *};
-use cal_rem_shared::{Entry, Todo};
-use crate::calendar::{future_calendar_nodes_from_entries, todays_date_description};
-use crate::todo::sliding_todo;
+use chrono_tz::{Tz, Europe::Oslo};
+use cal_rem_shared::{Entry, Settings, Todo};
+use crate::calendar::{future_calendar_nodes_from_entries, tag_filter, todays_date_description};
+use crate::todo::{finished_todos, sliding_todo};
+
+// Build with CAL_REM_API_URL set to use another API, like the local development server.
+const API_URL: &str = match option_env!("CAL_REM_API_URL") {
+    Some(url) => url,
+    None => "https://97g5b34p9e.execute-api.eu-north-1.amazonaws.com/default"
+};
 
 fn init(_: Url, orders: &mut impl Orders<Msg>) -> Model {
+    orders.send_msg(Msg::SettingsRequest);
     orders.send_msg(Msg::CalendarEntryRequest);
     orders.send_msg(Msg::TodoEntryRequest);
 
-    Model { calendar_entries: vec![], todo_entries: vec![] }
+    Model { calendar_entries: vec![], todo_entries: vec![], selected_tag: None, time_zone: Oslo }
 }
 
 struct Model {
     calendar_entries: Vec<Entry>,
     todo_entries: Vec<Todo>,
+    selected_tag: Option<String>,
+    time_zone: Tz,
 }
 
 pub enum Msg {
+    SettingsRequest,
+    SettingsResponse(Settings),
     CalendarEntryRequest,
     CalendarEntryResponse(Vec<Entry>),
     TodoEntryRequest,
     TodoEntryResponse(Vec<Todo>),
+    SelectTag(Option<String>),
 }
 
 fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
     match msg {
+        Msg::SettingsRequest => {
+            orders.skip().perform_cmd(async {
+                let req = Request::new(format!("{}/get-settings", API_URL)).method(Method::Get);
+                let response = req.fetch().await.expect("HTTP request failed");
+                let response = response.check_status().expect("status failed").json().await.expect("deserialization failed");
+                Msg::SettingsResponse(response)
+            });
+        },
+        Msg::SettingsResponse(settings) => {
+            model.time_zone = settings.home_time_zone();
+        },
         Msg::CalendarEntryRequest => {
             orders.skip().perform_cmd(async {
-                let req = Request::new("https://97g5b34p9e.execute-api.eu-north-1.amazonaws.com/default/get-all-calendar-entries").method(Method::Get);
+                let req = Request::new(format!("{}/get-all-calendar-entries", API_URL)).method(Method::Get);
                 let response = req.fetch().await.expect("HTTP request failed");
                 let response = response.check_status().expect("status failed").json().await.expect("deserialization failed");
                 Msg::CalendarEntryResponse(response)
@@ -42,7 +66,7 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         },
         Msg::TodoEntryRequest => {
             orders.skip().perform_cmd(async {
-                let req = Request::new("https://97g5b34p9e.execute-api.eu-north-1.amazonaws.com/default/get-all-todo-entries").method(Method::Get);
+                let req = Request::new(format!("{}/get-all-todo-entries", API_URL)).method(Method::Get);
                 let response = req.fetch().await.expect("HTTP request failed");
                 let response = response.check_status().expect("status failed").json().await.expect("deserialization failed");
                 Msg::TodoEntryResponse(response)
@@ -50,6 +74,9 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         },
         Msg::TodoEntryResponse(response) => {
             model.todo_entries = response;
+        },
+        Msg::SelectTag(tag) => {
+            model.selected_tag = tag;
         }
     }
 }
@@ -61,8 +88,10 @@ fn view(model: &Model) -> Node<Msg> {
         sliding_todo(&model.todo_entries),
         div![
             style!{St::Margin => px(16)},
-            span![ todays_date_description() ],
-            future_calendar_nodes_from_entries(&model.calendar_entries),
+            span![ todays_date_description(model.time_zone) ],
+            tag_filter(&model.selected_tag),
+            future_calendar_nodes_from_entries(&model.calendar_entries, &model.selected_tag, model.time_zone),
+            finished_todos(&model.todo_entries),
         ]
     ]
 
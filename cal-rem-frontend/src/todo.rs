@@ -1,16 +1,44 @@
 use seed::{prelude::*, *};
-use cal_rem_shared::Todo;
+use cal_rem_shared::{Todo, month_to_num};
 use crate::Msg;
 
 pub fn sliding_todo(todo_list: &Vec<Todo>) -> Node<Msg> {
     let todo_string = todo_list.iter()
-        .filter(|todo| todo.done)
+        .filter(|todo| !todo.done)
         .map(|todo| format!("{} --------- ", todo.description))
         .collect::<Vec<String>>().join("");
 
     marquee(&todo_string)
 }
 
+// todo.txt keeps the most recently finished todos first.
+pub fn finished_todos(todo_list: &Vec<Todo>) -> Option<Node<Msg>> {
+    let finished: Vec<&Todo> = todo_list.iter().filter(|todo| todo.done).take(5).collect();
+
+    if finished.is_empty() {
+        return None;
+    }
+
+    Some(div![
+        style!{St::MarginTop => px(24)},
+        style!{St::FontSize => px(12)},
+        h2![
+            "Ferdig",
+            style!{St::FontSize => px(16)}
+        ],
+        finished.iter().map(|todo| div![
+            span![
+                todo.description.clone(),
+                style!{St::TextDecoration => "line-through"},
+            ],
+            todo.completed.map(|completed| span![
+                format!(" {}.{}", completed.date, month_to_num(completed.month)),
+                style!{St::Color => "#888"},
+            ]),
+        ])
+    ])
+}
+
 fn marquee(content: &str) -> Node<Msg> {
     let content = format!("{}{}{}{}", content, content, content, content);
     div![
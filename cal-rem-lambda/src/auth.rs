@@ -0,0 +1,119 @@
+use sha2::{Digest, Sha256};
+use std::env::var;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum AuthError {
+    // no token, or one that isn't configured.
+    Unauthorized,
+    // a read-only token used for a write.
+    Forbidden,
+}
+
+pub struct AuthConfig {
+    pub write_keys: Vec<String>,
+    pub read_keys: Vec<String>,
+    // None allows every origin.
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+/// Reads the API keys and CORS origins from the environment, all as comma separated lists:
+/// API_KEYS may read and write, READ_API_KEYS may only read, and CORS_ALLOWED_ORIGINS
+/// replaces the `*` origin. Reads stay open to everyone unless READ_API_KEYS is set.
+pub fn get_auth_config() -> AuthConfig {
+    AuthConfig {
+        write_keys: list_from_env("API_KEYS").unwrap_or_default(),
+        read_keys: list_from_env("READ_API_KEYS").unwrap_or_default(),
+        allowed_origins: list_from_env("CORS_ALLOWED_ORIGINS"),
+    }
+}
+
+fn list_from_env(name: &str) -> Option<Vec<String>> {
+    var(name).ok().map(|list| list.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect())
+}
+
+impl AuthConfig {
+    /// Checks an `Authorization: Bearer <key>` header against the configured keys.
+    pub fn authorize(&self, authorization: Option<&str>, access: Access) -> Result<(), AuthError> {
+        if access == Access::Read && self.read_keys.is_empty() {
+            return Ok(());
+        }
+
+        let token = authorization
+            .and_then(|authorization| authorization.trim().strip_prefix("Bearer "))
+            .map(|token| token.trim())
+            .ok_or(AuthError::Unauthorized)?;
+
+        if self.write_keys.iter().any(|key| constant_time_eq(key, token)) {
+            Ok(())
+        } else if self.read_keys.iter().any(|key| constant_time_eq(key, token)) {
+            if access == Access::Read { Ok(()) } else { Err(AuthError::Forbidden) }
+        } else {
+            Err(AuthError::Unauthorized)
+        }
+    }
+
+    /// The value for Access-Control-Allow-Origin, or None if the origin isn't allowed.
+    pub fn allowed_origin(&self, origin: Option<&str>) -> Option<String> {
+        match &self.allowed_origins {
+            None => Some("*".to_string()),
+            Some(allowed_origins) => origin.filter(|origin| allowed_origins.iter().any(|allowed| allowed == origin)).map(|origin| origin.to_string())
+        }
+    }
+}
+
+// compares every byte of digests that are always the same length, so the time taken doesn't give
+// away how much of a key was right, or how long it is.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    a.iter().zip(b.iter()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            write_keys: vec!["write-key".to_string()],
+            read_keys: vec!["read-key".to_string()],
+            allowed_origins: Some(vec!["https://calendar.example".to_string()]),
+        }
+    }
+
+    #[test]
+    fn authorize_test() {
+        let config = config();
+        assert_eq!(Ok(()), config.authorize(Some("Bearer write-key"), Access::Write));
+        assert_eq!(Ok(()), config.authorize(Some("Bearer write-key"), Access::Read));
+        assert_eq!(Ok(()), config.authorize(Some("Bearer read-key"), Access::Read));
+        assert_eq!(Err(AuthError::Forbidden), config.authorize(Some("Bearer read-key"), Access::Write));
+        assert_eq!(Err(AuthError::Unauthorized), config.authorize(Some("Bearer wrong-key"), Access::Read));
+        assert_eq!(Err(AuthError::Unauthorized), config.authorize(Some("Bearer write-key-2"), Access::Read));
+        assert_eq!(Err(AuthError::Unauthorized), config.authorize(Some("write-key"), Access::Write));
+        assert_eq!(Err(AuthError::Unauthorized), config.authorize(None, Access::Write));
+
+        let open_reads = AuthConfig { read_keys: vec![], ..config };
+        assert_eq!(Ok(()), open_reads.authorize(None, Access::Read));
+        assert_eq!(Err(AuthError::Unauthorized), open_reads.authorize(None, Access::Write));
+
+        let no_keys = AuthConfig { write_keys: vec![], read_keys: vec![], allowed_origins: None };
+        assert_eq!(Err(AuthError::Unauthorized), no_keys.authorize(Some("Bearer "), Access::Write));
+    }
+
+    #[test]
+    fn allowed_origin_test() {
+        let config = config();
+        assert_eq!(Some("https://calendar.example".to_string()), config.allowed_origin(Some("https://calendar.example")));
+        assert_eq!(None, config.allowed_origin(Some("https://elsewhere.example")));
+        assert_eq!(None, config.allowed_origin(None));
+
+        let any_origin = AuthConfig { allowed_origins: None, ..config };
+        assert_eq!(Some("*".to_string()), any_origin.allowed_origin(Some("https://elsewhere.example")));
+    }
+}
@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use chrono::{prelude::*, Duration};
+use lambda_runtime::Error;
+use std::collections::HashMap;
+use std::env::var;
+use std::sync::Mutex;
+use crate::dynamodb::DynamoDbCache;
+
+/// Small string values that outlive a single run, like when the notifier last ran.
+#[async_trait]
+pub trait Cache: Send + Sync {
+    /// None if the key isn't set or has expired.
+    async fn get(&self, key: &str) -> Result<Option<String>, Error>;
+
+    /// Sets the key, to expire after `ttl` if given.
+    async fn put(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<(), Error>;
+
+    /// Sets the key only if it still has the `expected` value, None meaning that it isn't set,
+    /// and returns whether it was set.
+    async fn compare_and_swap(&self, key: &str, expected: Option<&str>, value: String, ttl: Option<Duration>) -> Result<bool, Error>;
+}
+
+/// The DynamoDB table CACHE_TABLE, which defaults to Cache.
+pub fn get_cache() -> Box<dyn Cache> {
+    Box::new(DynamoDbCache::new(var("CACHE_TABLE").unwrap_or_else(|_| "Cache".to_string())))
+}
+
+// Each value with when it expires.
+type Values = HashMap<String, (String, Option<DateTime<Utc>>)>;
+
+/// Values in memory, for tests.
+#[derive(Default)]
+pub struct MemoryCache {
+    values: Mutex<Values>,
+}
+
+impl MemoryCache {
+    fn current_value(values: &Values, key: &str) -> Option<String> {
+        values.get(key)
+            .filter(|(_, expires)| expires.map_or(true, |expires| expires > Utc::now()))
+            .map(|(value, _)| value.clone())
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        Ok(MemoryCache::current_value(&self.values.lock().unwrap(), key))
+    }
+
+    async fn put(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<(), Error> {
+        self.values.lock().unwrap().insert(key.to_string(), (value, ttl.map(|ttl| Utc::now() + ttl)));
+        Ok(())
+    }
+
+    async fn compare_and_swap(&self, key: &str, expected: Option<&str>, value: String, ttl: Option<Duration>) -> Result<bool, Error> {
+        let mut values = self.values.lock().unwrap();
+        if MemoryCache::current_value(&values, key).as_deref() != expected {
+            return Ok(false);
+        }
+
+        values.insert(key.to_string(), (value, ttl.map(|ttl| Utc::now() + ttl)));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_cache_test() {
+        let cache = MemoryCache::default();
+        assert_eq!(None, cache.get("last-notification-time").await.unwrap());
+
+        assert!(cache.compare_and_swap("last-notification-time", None, "100".to_string(), None).await.unwrap());
+        assert!(!cache.compare_and_swap("last-notification-time", None, "200".to_string(), None).await.unwrap());
+        assert!(!cache.compare_and_swap("last-notification-time", Some("150"), "200".to_string(), None).await.unwrap());
+        assert!(cache.compare_and_swap("last-notification-time", Some("100"), "200".to_string(), None).await.unwrap());
+        assert_eq!(Some("200".to_string()), cache.get("last-notification-time").await.unwrap());
+
+        cache.put("token", "abc".to_string(), Some(Duration::hours(1))).await.unwrap();
+        assert_eq!(Some("abc".to_string()), cache.get("token").await.unwrap());
+        cache.put("token", "abc".to_string(), Some(Duration::zero())).await.unwrap();
+        assert_eq!(None, cache.get("token").await.unwrap());
+        assert!(cache.compare_and_swap("token", None, "def".to_string(), None).await.unwrap());
+    }
+}
@@ -1,21 +1,315 @@
+use chrono::prelude::*;
 use lambda_runtime::Error;
-use std::env::var;
-use crate::{get_default_headers, Header, Response};
+use serde::Serialize;
+use std::collections::HashMap;
+use cal_rem_shared::Entry;
+use crate::{cached_data_response, editable_data_or_precondition_response, get_default_headers, precondition_failed_response, Header, Response};
+use crate::editor::{check_writable, insert_entry, merge_entries, remove_entry, replace_entry};
+use crate::ics::{calendar_to_ics, ics_to_entries};
 use crate::parser::parse_calendar_file;
-use crate::s3::{BrowserCachedData, get_object_as_string_if_etags_differ};
+use crate::query::CalendarQuery;
+use crate::settings::get_home_time_zone;
+use crate::storage::{ConditionalRequest, Storage, StoredObject, get_object_as_string_if_etag_matches, get_object_as_string_if_modified, get_object_variant_as_string_if_modified};
 
-pub async fn get_calendar_events(etag: Option<String>) -> Result<Response, Error> {
-    let cached_data = get_object_as_string_if_etags_differ(var("S3_MAIN_BUCKET")?, "calendar.txt".to_string(), etag).await?;
+/// See CalendarQuery for the query parameters.
+pub async fn get_calendar_events(storage: &dyn Storage, conditions: &ConditionalRequest, parameters: &HashMap<String, String>) -> Result<Response, Error> {
+    let query = match CalendarQuery::from_parameters(parameters) {
+        Ok(query) => query,
+        Err(message) => return Ok(Response { status_code: 400, headers: get_default_headers(), body: message})
+    };
+    let time_zone = get_home_time_zone()?;
+    let today = Utc::now().with_timezone(&time_zone).naive_local().date();
+    // which entries are in the past changes at midnight.
+    let midnight = time_zone.from_local_datetime(&today.and_hms_opt(0, 0, 0).unwrap()).earliest().map_or_else(Utc::now, |midnight| midnight.with_timezone(&Utc));
+
+    let cached_data = get_object_variant_as_string_if_modified(storage, "calendar.txt", conditions, &query.cache_key(today), midnight).await?;
+
+    cached_data_response(cached_data, get_default_headers(), |data| {
+        Ok(serde_json::to_string(&query.apply(&parse_calendar_file(&data).entries, today))?)
+    })
+}
+
+/// calendar.txt as an iCalendar feed that calendar apps can subscribe to.
+pub async fn get_calendar_ics(storage: &dyn Storage, conditions: &ConditionalRequest) -> Result<Response, Error> {
+    let cached_data = get_object_as_string_if_modified(storage, "calendar.txt", conditions).await?;
 
     let mut headers = get_default_headers();
+    headers.insert(Header::ContentType, "text/calendar; charset=utf-8".to_string());
+
+    cached_data_response(cached_data, headers, |data| {
+        Ok(calendar_to_ics(&parse_calendar_file(&data).entries, get_home_time_zone()?, Utc::now()))
+    })
+}
+
+pub async fn validate_calendar(storage: &dyn Storage, conditions: &ConditionalRequest) -> Result<Response, Error> {
+    let cached_data = get_object_as_string_if_modified(storage, "calendar.txt", conditions).await?;
 
-    Ok(match cached_data {
-        BrowserCachedData::InCache => {
-            Response { status_code: 304, headers, body: "".to_string()}
-        },
-        BrowserCachedData::NotInCache { data, etag } => {
-            headers.insert(Header::ETag, etag);
-            Response { status_code: 200, headers, body: serde_json::to_string(&parse_calendar_file(&data))?}
-        }
+    cached_data_response(cached_data, get_default_headers(), |data| {
+        Ok(serde_json::to_string(&parse_calendar_file(&data).diagnostics)?)
     })
-}
\ No newline at end of file
+}
+
+/// Adds the entry in the request body to calendar.txt and returns it with its id.
+pub async fn add_calendar_entry(storage: &dyn Storage, if_match: Option<String>, body: Option<String>) -> Result<Response, Error> {
+    let entry = match writable_entry(body) {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response)
+    };
+
+    let calendar = match editable_calendar(storage, if_match).await? {
+        Ok(calendar) => calendar,
+        Err(response) => return Ok(response)
+    };
+    let (data, line) = insert_entry(&calendar.data, &entry);
+    save_calendar_entry(storage, data, &calendar.etag, line).await
+}
+
+/// Replaces the entry with the given id. The id stays the same unless the body gives a new one.
+/// Occurrences of a recurring entry are all on its line, so the id of one of them replaces the
+/// recurring entry, and the body has to be the recurring entry with its recurrence. An occurrence
+/// as it was read has none, and writing it would end the series, so that's a conflict.
+pub async fn update_calendar_entry(storage: &dyn Storage, id: &str, if_match: Option<String>, body: Option<String>) -> Result<Response, Error> {
+    let mut entry = match writable_entry(body) {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response)
+    };
+
+    let calendar = match editable_calendar(storage, if_match).await? {
+        Ok(calendar) => calendar,
+        Err(response) => return Ok(response)
+    };
+    let (line, entry_id) = match find_entry_line(&calendar.data, id) {
+        Some(found) => found,
+        None => return Ok(Response { status_code: 404, headers: get_default_headers(), body: "Entry not found".to_string()})
+    };
+    if entry_id != id && entry.recurrence.is_none() {
+        return Ok(occurrence_conflict_response());
+    }
+
+    if entry.id.is_empty() || entry.id == id {
+        entry.id = entry_id;
+    }
+
+    let (data, line) = replace_entry(&calendar.data, line, &entry);
+    save_calendar_entry(storage, data, &calendar.etag, line).await
+}
+
+/// Deletes the entry with the given id. Occurrences can't be deleted on their own, so the id of
+/// one is a conflict, and the recurring entry has to be deleted by its own id.
+pub async fn delete_calendar_entry(storage: &dyn Storage, id: &str, if_match: Option<String>) -> Result<Response, Error> {
+    let calendar = match editable_calendar(storage, if_match).await? {
+        Ok(calendar) => calendar,
+        Err(response) => return Ok(response)
+    };
+    let line = match find_entry_line(&calendar.data, id) {
+        Some((line, entry_id)) if entry_id == id => line,
+        Some(_) => return Ok(occurrence_conflict_response()),
+        None => return Ok(Response { status_code: 404, headers: get_default_headers(), body: "Entry not found".to_string()})
+    };
+
+    let etag = match save_calendar(storage, remove_entry(&calendar.data, line), &calendar.etag).await? {
+        Ok(etag) => etag,
+        Err(response) => return Ok(response)
+    };
+    let mut headers = get_default_headers();
+    headers.insert(Header::ETag, etag);
+    Ok(Response { status_code: 204, headers, body: "".to_string()})
+}
+
+#[derive(Serialize)]
+struct ImportReport {
+    imported: Vec<Entry>,
+    duplicates: Vec<Entry>,
+    warnings: Vec<String>,
+}
+
+/// Adds the events of the .ics file in the request body to calendar.txt, leaving out the ones
+/// already there, and reports what was imported and what couldn't be.
+pub async fn import_ics(storage: &dyn Storage, if_match: Option<String>, body: Option<String>) -> Result<Response, Error> {
+    let calendar = match editable_calendar(storage, if_match).await? {
+        Ok(calendar) => calendar,
+        Err(response) => return Ok(response)
+    };
+
+    let (entries, warnings) = ics_to_entries(&body.unwrap_or_default(), get_home_time_zone()?);
+    let (data, imported, duplicates) = merge_entries(&calendar.data, entries);
+    // the entries as they read back from the file, with the ids the parser gives them, which may
+    // have a -2 or -3 on the end. Duplicates are the entries already in the file.
+    let saved = parse_calendar_file(&data).entries;
+    let as_saved = |entries: Vec<Entry>| entries.into_iter().map(|entry| {
+        let content_id = entry.content_id();
+        saved.iter().find(|saved| saved.content_id() == content_id).cloned().unwrap_or(Entry { id: content_id, ..entry })
+    }).collect();
+    let report = ImportReport { imported: as_saved(imported), duplicates: as_saved(duplicates), warnings };
+
+    let mut headers = get_default_headers();
+    if !report.imported.is_empty() {
+        match save_calendar(storage, data, &calendar.etag).await? {
+            Ok(etag) => headers.insert(Header::ETag, etag),
+            Err(response) => return Ok(response)
+        };
+    }
+
+    Ok(Response { status_code: 200, headers, body: serde_json::to_string(&report)? })
+}
+
+// Reads an entry from a request body, and refuses entries that wouldn't read back the same
+// from calendar.txt.
+fn writable_entry(body: Option<String>) -> Result<Entry, Response> {
+    let bad_request = |message: String| Response { status_code: 400, headers: get_default_headers(), body: message };
+
+    let mut entry: Entry = serde_json::from_str(&body.unwrap_or_default()).map_err(|error| bad_request(format!("Invalid entry: {}", error)))?;
+    // entries read from the API may be occurrences, but which series they're from isn't written to the file.
+    entry.series_id = None;
+    check_writable(&entry).map_err(|message| bad_request(format!("Invalid entry: {}", message)))?;
+
+    Ok(entry)
+}
+
+async fn editable_calendar(storage: &dyn Storage, if_match: Option<String>) -> Result<Result<StoredObject, Response>, Error> {
+    let editable_data = get_object_as_string_if_etag_matches(storage, "calendar.txt", if_match).await?;
+    Ok(editable_data_or_precondition_response(editable_data))
+}
+
+// Saves calendar.txt unless it has changed since it was read with the given ETag, and returns the new ETag.
+async fn save_calendar(storage: &dyn Storage, data: String, etag: &str) -> Result<Result<String, Response>, Error> {
+    Ok(storage.put_if_match("calendar.txt", data, etag).await?.ok_or_else(precondition_failed_response))
+}
+
+// The line of the entry with the id, and the id of the entry on it, which for the id of an
+// occurrence is the recurring entry's.
+fn find_entry_line(data: &String, id: &str) -> Option<(usize, String)> {
+    let parsed = parse_calendar_file(data);
+    let index = parsed.entries.iter().position(|entry| entry.id == id)
+        .or_else(|| parsed.entries.iter().position(|entry| entry.recurrence.is_some() && is_occurrence_id(id, &entry.id)))?;
+
+    Some((parsed.lines[index], parsed.entries[index].id.clone()))
+}
+
+fn occurrence_conflict_response() -> Response {
+    Response { status_code: 409, headers: get_default_headers(), body: "The entry is an occurrence of a recurring entry, which has to be written as a whole".to_string()}
+}
+
+// Occurrences are given the recurring entry's id followed by their date, like abc-20210615.
+fn is_occurrence_id(id: &str, series_id: &str) -> bool {
+    id.strip_prefix(series_id)
+        .and_then(|date| date.strip_prefix('-'))
+        .map_or(false, |date| date.len() == 8 && date.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+async fn save_calendar_entry(storage: &dyn Storage, data: String, etag: &str, line: usize) -> Result<Response, Error> {
+    let parsed = parse_calendar_file(&data);
+    let entry = parsed.lines.iter().position(|entry_line| *entry_line == line).map(|index| &parsed.entries[index]);
+    let body = serde_json::to_string(&entry)?;
+
+    let etag = match save_calendar(storage, data, etag).await? {
+        Ok(etag) => etag,
+        Err(response) => return Ok(response)
+    };
+    let mut headers = get_default_headers();
+    headers.insert(Header::ETag, etag);
+    Ok(Response { status_code: 200, headers, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    const CALENDAR: &str = "2021\n\nJuni\n1. Trening {ukentlig}\n15. Tannlege\n";
+
+    async fn occurrence_ids(storage: &dyn Storage) -> Vec<String> {
+        let parameters = vec![("from".to_string(), "2021-06-01".to_string()), ("to".to_string(), "2021-06-10".to_string())].into_iter().collect();
+        let response = get_calendar_events(storage, &ConditionalRequest::default(), &parameters).await.unwrap();
+        serde_json::from_str::<Vec<Entry>>(&response.body).unwrap().into_iter().map(|entry| entry.id).collect()
+    }
+
+    #[tokio::test]
+    async fn write_after_reading_entries_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", CALENDAR);
+        let response = get_calendar_events(&storage, &ConditionalRequest::default(), &HashMap::new()).await.unwrap();
+        let etag = response.headers[&Header::ETag].clone();
+
+        let entry = r#"{"description": "Frisør", "location": null, "year": 2021, "month": "June", "start_date": 20, "end_date": null,
+            "end_month": null, "end_year": null, "start_time": null, "end_time": null, "time_zone": null, "recurrence": null, "reminders": null}"#;
+        let response = add_calendar_entry(&storage, Some(etag.clone()), Some(entry.to_string())).await.unwrap();
+        assert_eq!(200, response.status_code);
+        assert_eq!("2021\n\nJuni\n1. Trening {ukentlig}\n15. Tannlege\n20. Frisør\n", storage.object("calendar.txt").unwrap());
+
+        // the entries read before the write are out of date.
+        let response = add_calendar_entry(&storage, Some(etag), Some(entry.to_string())).await.unwrap();
+        assert_eq!(412, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn import_ics_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", CALENDAR);
+        let etag = storage.get("calendar.txt").await.unwrap().etag;
+        let ics = [
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "SUMMARY:Tannlege",
+            "DTSTART;VALUE=DATE:20210615",
+            "DTEND;VALUE=DATE:20210616",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Frisør",
+            "DTSTART;VALUE=DATE:20210620",
+            "DTEND;VALUE=DATE:20210621",
+            "END:VEVENT",
+            "END:VCALENDAR",
+            "",
+        ].join("\r\n");
+
+        let response = import_ics(&storage, Some(etag), Some(ics)).await.unwrap();
+        assert_eq!(200, response.status_code);
+        assert_eq!("2021\n\nJuni\n1. Trening {ukentlig}\n15. Tannlege\n20. Frisør\n", storage.object("calendar.txt").unwrap());
+
+        let report: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        let ids = |entries: &serde_json::Value| entries.as_array().unwrap().iter().map(|entry| entry["id"].as_str().unwrap().to_string()).collect::<Vec<String>>();
+        let saved_ids: Vec<String> = parse_calendar_file(&storage.object("calendar.txt").unwrap()).entries.into_iter().map(|entry| entry.id).collect();
+        assert_eq!(vec![saved_ids[2].clone()], ids(&report["imported"]));
+        assert_eq!(vec![saved_ids[1].clone()], ids(&report["duplicates"]));
+    }
+
+    #[tokio::test]
+    async fn write_occurrence_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", CALENDAR);
+        let ids = occurrence_ids(&storage).await;
+        assert_eq!(2, ids.len());
+
+        let etag = storage.get("calendar.txt").await.unwrap().etag;
+        let series = r#"{"description": "Trening", "location": "Gymmen", "year": 2021, "month": "June", "start_date": 1, "end_date": null,
+            "end_month": null, "end_year": null, "start_time": null, "end_time": null, "time_zone": null,
+            "recurrence": {"frequency": {"Weekly": {"interval": 1}}, "limit": null}, "reminders": null}"#;
+        let response = update_calendar_entry(&storage, &ids[1], Some(etag), Some(series.to_string())).await.unwrap();
+        assert_eq!(200, response.status_code);
+        assert_eq!("2021\n\nJuni\n1. Trening @ Gymmen {ukentlig}\n15. Tannlege\n", storage.object("calendar.txt").unwrap());
+
+        let etag = storage.get("calendar.txt").await.unwrap().etag;
+        let response = delete_calendar_entry(&storage, &ids[0], Some(etag.clone())).await.unwrap();
+        assert_eq!(409, response.status_code);
+
+        let response = delete_calendar_entry(&storage, &format!("{}-2021", ids[0]), Some(etag)).await.unwrap();
+        assert_eq!(404, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn write_occurrence_as_read_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", CALENDAR);
+        let parameters = vec![("from".to_string(), "2021-06-08".to_string()), ("to".to_string(), "2021-06-08".to_string())].into_iter().collect();
+        let response = get_calendar_events(&storage, &ConditionalRequest::default(), &parameters).await.unwrap();
+        let etag = response.headers[&Header::ETag].clone();
+        let occurrence = serde_json::from_str::<Vec<Entry>>(&response.body).unwrap().remove(0);
+
+        let response = update_calendar_entry(&storage, &occurrence.id, Some(etag), Some(serde_json::to_string(&occurrence).unwrap())).await.unwrap();
+        assert_eq!(409, response.status_code);
+        assert_eq!(CALENDAR, storage.object("calendar.txt").unwrap());
+
+        let series_id = occurrence.series_id.unwrap();
+        let etag = storage.get("calendar.txt").await.unwrap().etag;
+        let response = delete_calendar_entry(&storage, &series_id, Some(etag)).await.unwrap();
+        assert_eq!(204, response.status_code);
+        assert_eq!("2021\n\nJuni\n15. Tannlege\n", storage.object("calendar.txt").unwrap());
+    }
+}
@@ -0,0 +1,161 @@
+// Serves the API over plain HTTP with calendar.txt and todo.txt read from and written to a local
+// directory, so the frontend can be developed without AWS:
+//
+//     dev-server [data directory] [--port 3000]
+//
+// Build the frontend with CAL_REM_API_URL=http://localhost:3000 to use it. POST /run-notifier runs
+// the notifier like the scheduled CloudWatch event does, with the cache kept in memory for as long
+// as the server runs. It prints the notifications before sending them, which needs the MATRIX_
+// variables.
+use lambda_runtime::Error;
+use std::collections::HashMap;
+use std::env::args;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use cal_rem_lambda::{ApiGatewayRequest, Response, handle_request};
+use cal_rem_lambda::cache::MemoryCache;
+use cal_rem_lambda::notifier::{get_messenger, run_notifier};
+use cal_rem_lambda::storage::FileStorage;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args: Vec<String> = args().skip(1).collect();
+    let port = match args.iter().position(|arg| arg == "--port") {
+        Some(index) => args.get(index + 1).ok_or("--port needs a number")?.parse::<u16>()?,
+        None => 3000
+    };
+    let dir = args.iter().enumerate()
+        .find(|(index, arg)| !arg.starts_with("--") && (*index == 0 || args[index - 1] != "--port"))
+        .map_or(".".to_string(), |(_, arg)| arg.clone());
+
+    let storage = FileStorage { dir: PathBuf::from(&dir) };
+    let cache = MemoryCache::default();
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("Serving {} on http://localhost:{}", dir, port);
+
+    // one request at a time is plenty for a single developer.
+    loop {
+        let (stream, _) = listener.accept().await?;
+        if let Err(error) = serve(stream, &storage, &cache).await {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, storage: &FileStorage, cache: &MemoryCache) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(format!("Invalid request line: {:?}", request_line).into())
+    };
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => { headers.insert(name.trim().to_lowercase(), value.trim().to_string()); },
+            None => break
+        }
+    }
+
+    let mut body = vec![0; headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0)];
+    reader.read_exact(&mut body).await?;
+
+    let (path, query) = parse_target(&target);
+    // HEAD is answered as a GET without the body, so Content-Length is the length of the GET's body.
+    let head = method.eq_ignore_ascii_case("HEAD");
+
+    let response = if method == "POST" && path == "/run-notifier" {
+        run_notifier(storage, cache, get_messenger().as_ref()).await.map(|_| Response { status_code: 204, headers: HashMap::new(), body: "".to_string() })
+    } else {
+        let request = ApiGatewayRequest {
+            body: if body.is_empty() { None } else { Some(String::from_utf8_lossy(&body).to_string()) },
+            headers,
+            http_method: if head { "GET".to_string() } else { method.clone() },
+            path,
+            query_string_parameters: None,
+            multi_value_query_string_parameters: Some(query),
+        };
+        handle_request(request, storage).await
+    };
+    let response = response.unwrap_or_else(|error| {
+        Response { status_code: 500, headers: HashMap::new(), body: error.to_string() }
+    });
+    println!("{} {} {}", method, target, response.status_code);
+
+    let mut response_head = format!("HTTP/1.1 {} \r\n", response.status_code);
+    for (header, value) in &response.headers {
+        response_head.push_str(&format!("{}: {}\r\n", serde_json::to_string(header)?.trim_matches('"'), value));
+    }
+    response_head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+
+    let stream = reader.get_mut();
+    stream.write_all(response_head.as_bytes()).await?;
+    if !head {
+        stream.write_all(response.body.as_bytes()).await?;
+    }
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+// Splits a request target like /get-all-calendar-entries?tag=jobb&tag=ferie into the path and the
+// values of each query parameter.
+fn parse_target(target: &str) -> (String, HashMap<String, Vec<String>>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut parameters: HashMap<String, Vec<String>> = HashMap::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        parameters.entry(percent_decode(&name.replace('+', " "))).or_default().push(percent_decode(&value.replace('+', " ")));
+    }
+
+    (percent_decode(path), parameters)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let hex = |index: usize| bytes.get(index..index + 2)
+        .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        match (bytes[index], hex(index + 1)) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 2;
+            },
+            (byte, _) => decoded.push(byte)
+        }
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_target_test() {
+        let (path, query) = parse_target("/get-all-calendar-entries?tag=jobb&tag=%23ferie&q=tann+lege&include_past");
+        assert_eq!("/get-all-calendar-entries", path);
+        assert_eq!(Some(&vec!["jobb".to_string(), "#ferie".to_string()]), query.get("tag"));
+        assert_eq!(Some(&vec!["tann lege".to_string()]), query.get("q"));
+        assert_eq!(Some(&vec!["".to_string()]), query.get("include_past"));
+
+        assert_eq!(("/calendar-entries/a b+c".to_string(), HashMap::new()), parse_target("/calendar-entries/a%20b+c"));
+        assert_eq!("100%", percent_decode("100%"));
+        assert_eq!("%zz", percent_decode("%zz"));
+    }
+}
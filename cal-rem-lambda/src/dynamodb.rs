@@ -1,27 +1,101 @@
-use dynamodb::{Client, model::AttributeValue};
+use async_trait::async_trait;
+use chrono::{prelude::*, Duration};
+use dynamodb::{Client, SdkError, model::AttributeValue};
 use lambda_runtime::Error;
+use std::collections::HashMap;
+use crate::cache::Cache;
 
-pub async fn get_value_from_cache(key: String) -> Result<Option<String>, Error> {
-    let client = Client::from_env();
-    let resp = client.get_item().table_name("Cache").key("key", AttributeValue::S(key)).send().await?;
-
-    match resp.item {
-        Some(map) => {
-            Ok(Some(map
-                .get("value").expect("Existing key in cache must have the attr. 'value'")
-                .as_s().expect("All simple keys in cache should be of the type string.")
-                .clone()))
-        },
-        None => Ok(None)
+/// A table with the string attributes `key` (the partition key) and `value`, and `expires` in
+/// epoch seconds, which should be the table's TTL attribute.
+pub struct DynamoDbCache {
+    client: Client,
+    table: String,
+}
+
+impl DynamoDbCache {
+    pub fn new(table: String) -> DynamoDbCache {
+        DynamoDbCache { client: Client::from_env(), table }
+    }
+}
+
+// DynamoDB deletes expired items within a couple of days, so they can still be read for a while.
+fn current_value(item: &HashMap<String, AttributeValue>) -> Result<Option<String>, Error> {
+    let expires = match item.get("expires") {
+        Some(AttributeValue::N(expires)) => Some(expires.parse::<i64>()?),
+        Some(_) => return Err("The cache's expires attribute should be a number".into()),
+        None => None
+    };
+
+    if expires.map_or(false, |expires| expires <= Utc::now().timestamp()) {
+        return Ok(None);
+    }
+
+    match item.get("value") {
+        Some(AttributeValue::S(value)) => Ok(Some(value.clone())),
+        Some(_) => Err("The cache's value attribute should be a string".into()),
+        None => Err("The cache has a key without a value attribute".into())
+    }
+}
+
+enum Condition<'a> {
+    Always,
+    // None means that the key isn't set.
+    IfValue(Option<&'a str>),
+}
+
+impl DynamoDbCache {
+    // Returns whether the item was put.
+    async fn put_item(&self, key: &str, value: String, ttl: Option<Duration>, condition: Condition<'_>) -> Result<bool, Error> {
+        let mut request = self.client.put_item().table_name(&self.table)
+            .item("key", AttributeValue::S(key.to_string()))
+            .item("value", AttributeValue::S(value));
+        if let Some(ttl) = ttl {
+            request = request.item("expires", AttributeValue::N((Utc::now() + ttl).timestamp().to_string()));
+        }
+
+        // key and value are reserved words in condition expressions, and DynamoDB refuses names and values
+        // that the expression doesn't use.
+        if let Condition::IfValue(expected) = condition {
+            request = request
+                .expression_attribute_names("#expires", "expires")
+                .expression_attribute_values(":now", AttributeValue::N(Utc::now().timestamp().to_string()));
+
+            request = match expected {
+                Some(expected) => request
+                    .condition_expression("#value = :expected AND (attribute_not_exists(#expires) OR #expires > :now)")
+                    .expression_attribute_names("#value", "value")
+                    .expression_attribute_values(":expected", AttributeValue::S(expected.to_string())),
+                None => request
+                    .condition_expression("attribute_not_exists(#key) OR #expires <= :now")
+                    .expression_attribute_names("#key", "key")
+            };
+        }
+
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError { err, .. }) if err.is_conditional_check_failed_exception() => Ok(false),
+            Err(error) => Err(error.into())
+        }
     }
 }
 
-pub async fn store_value_in_cache(key: String, value: String) -> Result<(), Error> {
-    let client = Client::from_env();
-    client.put_item().table_name("Cache")
-        .item("key", AttributeValue::S(key))
-        .item("value", AttributeValue::S(value))
-        .send().await?;
+#[async_trait]
+impl Cache for DynamoDbCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let resp = self.client.get_item().table_name(&self.table).key("key", AttributeValue::S(key.to_string())).send().await?;
 
-    Ok(())
-}
\ No newline at end of file
+        match resp.item {
+            Some(item) => current_value(&item),
+            None => Ok(None)
+        }
+    }
+
+    async fn put(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<(), Error> {
+        self.put_item(key, value, ttl, Condition::Always).await?;
+        Ok(())
+    }
+
+    async fn compare_and_swap(&self, key: &str, expected: Option<&str>, value: String, ttl: Option<Duration>) -> Result<bool, Error> {
+        self.put_item(key, value, ttl, Condition::IfValue(expected)).await
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use cal_rem_shared::{Entry, Month, format_calendar_file, format_entry, month_name};
+use crate::parser::{month_regex, parse_calendar_file, year_regex};
+
+/// Adds an entry to calendar.txt under its year and month header, after the entries
+/// of that month dated on or before it. Missing headers are added in calendar order.
+/// Returns the new file and the line the entry ended up on, counted from 1.
+pub fn insert_entry(file: &str, entry: &Entry) -> (String, usize) {
+    // calendar.txt may have been saved with Windows line endings, which the added lines get too.
+    if file.contains("\r\n") {
+        let (file, line) = insert_entry(&file.replace("\r\n", "\n"), entry);
+        return (file.replace('\n', "\r\n"), line);
+    }
+
+    let mut lines: Vec<&str> = file.split('\n').collect();
+    let entry_line = format_entry(entry);
+    let year_line = entry.year.to_string();
+    let parsed = parse_calendar_file(&file.to_string());
+    let sections = sections(&lines);
+
+    let month_header = sections.iter().position(|section| *section == (Some(entry.year), Some(entry.month)));
+
+    if let Some(month_header) = month_header {
+        // undated entries go last in their month.
+        let date = entry.start_date.unwrap_or(u32::MAX);
+        let after = parsed.entries.iter().zip(&parsed.lines)
+            .filter(|(other, _)| other.year == entry.year && other.month == entry.month && other.start_date.unwrap_or(0) <= date)
+            .map(|(_, line)| line - 1)
+            .filter(|index| *index > month_header)
+            .max()
+            .unwrap_or(month_header);
+
+        lines.insert(after + 1, &entry_line);
+        return (lines.join("\n"), after + 2);
+    }
+
+    let year_header = lines.iter().position(|line| year_regex(line) == Some(entry.year));
+    let new_lines: Vec<&str>;
+    let index;
+
+    if let Some(year_header) = year_header {
+        let later_month = (year_header + 1..lines.len())
+            .take_while(|index| sections[*index].0 == Some(entry.year))
+            .find(|index| month_regex(lines[*index]).map_or(false, |month| month > entry.month));
+
+        match later_month {
+            Some(later_month) => {
+                index = later_month;
+                new_lines = vec![month_name(entry.month), &entry_line, ""];
+            },
+            None => {
+                let end = (year_header..lines.len()).take_while(|index| sections[*index].0 == Some(entry.year)).last().unwrap();
+                index = last_non_blank_line(&lines, year_header, end).unwrap() + 1;
+                new_lines = if index == year_header + 1 { vec![month_name(entry.month), &entry_line] } else { vec!["", month_name(entry.month), &entry_line] };
+            }
+        }
+    } else {
+        let later_year = lines.iter().position(|line| year_regex(line).map_or(false, |year| year > entry.year));
+
+        match later_year {
+            Some(later_year) => {
+                index = later_year;
+                new_lines = vec![&year_line, month_name(entry.month), &entry_line, ""];
+            },
+            None => {
+                let last_line = last_non_blank_line(&lines, 0, lines.len() - 1);
+                index = last_line.map_or(0, |line| line + 1);
+                new_lines = if last_line.is_none() { vec![&year_line, month_name(entry.month), &entry_line] } else { vec!["", &year_line, month_name(entry.month), &entry_line] };
+            }
+        }
+    }
+
+    let entry_index = index + new_lines.len() - if new_lines.last() == Some(&"") { 2 } else { 1 };
+    lines.splice(index..index, new_lines);
+    (lines.join("\n"), entry_index + 1)
+}
+
+/// Replaces the entry on `line` (counted from 1), moving it if its year or month changed.
+pub fn replace_entry(file: &str, line: usize, entry: &Entry) -> (String, usize) {
+    if file.contains("\r\n") {
+        let (file, line) = replace_entry(&file.replace("\r\n", "\n"), line, entry);
+        return (file.replace('\n', "\r\n"), line);
+    }
+
+    let mut lines: Vec<&str> = file.split('\n').collect();
+
+    if sections(&lines)[line - 1] == (Some(entry.year), Some(entry.month)) {
+        let entry_line = format_entry(entry);
+        lines[line - 1] = &entry_line;
+        (lines.join("\n"), line)
+    } else {
+        insert_entry(&remove_entry(file, line), entry)
+    }
+}
+
+/// Removes the entry on `line` (counted from 1) and leaves every other line as it was.
+pub fn remove_entry(file: &str, line: usize) -> String {
+    let mut lines: Vec<&str> = file.split('\n').collect();
+    lines.remove(line - 1);
+    lines.join("\n")
+}
+
+/// Adds the entries that aren't already in calendar.txt, where an entry is already there if one
+/// has the same date and description. Returns the new file, the added entries and the duplicates.
+pub fn merge_entries(file: &str, entries: Vec<Entry>) -> (String, Vec<Entry>, Vec<Entry>) {
+    let mut content_ids: HashSet<String> = parse_calendar_file(&file.to_string()).entries.iter().map(|entry| entry.content_id()).collect();
+    let mut file = file.to_string();
+    let mut added = vec![];
+    let mut duplicates = vec![];
+
+    for entry in entries {
+        if content_ids.insert(entry.content_id()) {
+            file = insert_entry(&file, &entry).0;
+            added.push(entry);
+        } else {
+            duplicates.push(entry);
+        }
+    }
+
+    (file, added, duplicates)
+}
+
+/// Checks that an entry reads back the same once it's written to calendar.txt, which it
+/// doesn't if, for example, its description has an @ in it.
+pub fn check_writable(entry: &Entry) -> Result<(), String> {
+    let parsed = parse_calendar_file(&format_calendar_file(std::slice::from_ref(entry)));
+
+    if let Some(diagnostic) = parsed.diagnostics.first() {
+        return Err(diagnostic.message.clone());
+    }
+
+    let same_entry = parsed.entries.first().map_or(false, |parsed| Entry { id: entry.id.clone(), ..parsed.clone() } == *entry);
+    if parsed.entries.len() != 1 || !same_entry {
+        return Err("it can't be written to calendar.txt as it is".to_string());
+    }
+
+    Ok(())
+}
+
+// The year and month each line is under, the same way parse_calendar_file reads them: a year
+// header keeps the month of the year before until the next month header.
+fn sections(lines: &[&str]) -> Vec<(Option<u32>, Option<Month>)> {
+    let mut year = None;
+    let mut month = None;
+
+    lines.iter().map(|line| {
+        if let Some(y) = year_regex(line) {
+            year = Some(y);
+        }
+        if let Some(m) = month_regex(line) {
+            month = Some(m);
+        }
+        (year, month)
+    }).collect()
+}
+
+fn last_non_blank_line(lines: &[&str], from: usize, to: usize) -> Option<usize> {
+    (from..=to).rev().find(|index| !lines[*index].trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALENDAR: &str = "Notes at the top\n\n2021\nMai\n3. A\n20. B\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n";
+
+    #[test]
+    fn insert_test() {
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n10. New\n20. B\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n".to_string(), 6),
+            insert_entry(CALENDAR, &Entry::on(2021, Month::May, Some(10), "New")));
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n20. B\n?. New\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n".to_string(), 7),
+            insert_entry(CALENDAR, &Entry::on(2021, Month::May, None, "New")));
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n20. B\n\nJuni\n1. New\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n".to_string(), 9),
+            insert_entry(CALENDAR, &Entry::on(2021, Month::June, Some(1), "New")));
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n20. B\n\nJuli\n1. C\n\nDesember\n1. New\n\n2023\nJanuar\n5. D\n".to_string(), 12),
+            insert_entry(CALENDAR, &Entry::on(2021, Month::December, Some(1), "New")));
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n20. B\n\nJuli\n1. C\n\n2022\nMars\n1. New\n\n2023\nJanuar\n5. D\n".to_string(), 13),
+            insert_entry(CALENDAR, &Entry::on(2022, Month::March, Some(1), "New")));
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n20. B\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n\n2024\nMai\n1. New\n".to_string(), 17),
+            insert_entry(CALENDAR, &Entry::on(2024, Month::May, Some(1), "New")));
+        assert_eq!(("2024\nMai\n1. New\n".to_string(), 3), insert_entry("", &Entry::on(2024, Month::May, Some(1), "New")));
+    }
+
+    #[test]
+    fn year_without_month_header_test() {
+        // the entries under 2022 are in December, like the month before.
+        let file = "2021\nDesember\n1. A\n\n2022\n5. B\n";
+        let (inserted, line) = insert_entry(file, &Entry::on(2022, Month::December, Some(10), "New"));
+        assert_eq!(("2021\nDesember\n1. A\n\n2022\n5. B\n10. New\n", 7), (inserted.as_str(), line));
+
+        let parsed = parse_calendar_file(&inserted);
+        assert_eq!(vec![7], parsed.lines[2..].to_vec());
+        assert_eq!((2022, Month::December, Some(10)), (parsed.entries[2].year, parsed.entries[2].month, parsed.entries[2].start_date));
+    }
+
+    #[test]
+    fn line_endings_test() {
+        let file = "2021\r\nMai\r\n3. A\r\n";
+        assert_eq!(("2021\r\nMai\r\n3. A\r\n10. New\r\n".to_string(), 4), insert_entry(file, &Entry::on(2021, Month::May, Some(10), "New")));
+        assert_eq!(("2021\r\nMai\r\n3. A\r\n\r\nJuni\r\n1. New\r\n".to_string(), 6), insert_entry(file, &Entry::on(2021, Month::June, Some(1), "New")));
+
+        let mut edited = Entry::on(2021, Month::May, Some(3), "A");
+        edited.location = Some("Home".to_string());
+        assert_eq!(("2021\r\nMai\r\n3. A @ Home\r\n".to_string(), 3), replace_entry(file, 3, &edited));
+    }
+
+    #[test]
+    fn replace_and_remove_test() {
+        let mut edited = Entry::on(2021, Month::May, Some(20), "B");
+        edited.location = Some("Home".to_string());
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n20. B @ Home\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n".to_string(), 6),
+            replace_entry(CALENDAR, 6, &edited));
+
+        let moved = Entry::on(2021, Month::July, Some(2), "B");
+        assert_eq!(("Notes at the top\n\n2021\nMai\n3. A\n\nJuli\n1. C\n2. B\n\n2023\nJanuar\n5. D\n".to_string(), 9),
+            replace_entry(CALENDAR, 6, &moved));
+
+        assert_eq!("Notes at the top\n\n2021\nMai\n3. A\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n", remove_entry(CALENDAR, 6));
+    }
+
+    #[test]
+    fn merge_test() {
+        let new = Entry::on(2021, Month::May, Some(10), "New");
+        let existing = Entry::on(2021, Month::May, Some(20), "B");
+
+        let (file, added, duplicates) = merge_entries(CALENDAR, vec![new.clone(), existing.clone(), new.clone()]);
+        assert_eq!("Notes at the top\n\n2021\nMai\n3. A\n10. New\n20. B\n\nJuli\n1. C\n\n2023\nJanuar\n5. D\n", file);
+        assert_eq!(vec![new.clone()], added);
+        assert_eq!(vec![existing, new], duplicates);
+    }
+
+    #[test]
+    fn writable_test() {
+        assert_eq!(Ok(()), check_writable(&Entry::on(2021, Month::May, Some(10), "New")));
+        assert!(check_writable(&Entry::on(2021, Month::May, Some(10), "Lunch @ home [12.00]")).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// ETags as clients send them back in If-None-Match and If-Match: a list of tags like "abc", W/"abc"
+// when something on the way, like compression, has weakened them, abc from clients that drop the
+// quotes, or * for any.
+
+/// The tags in the header without W/, quoted like the ETags S3 gives, and whether each was weak.
+fn entity_tags(header: &str) -> Vec<(String, bool)> {
+    header.split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| match tag.strip_prefix("W/") {
+            Some(tag) => (quoted(tag), true),
+            None => (quoted(tag), false)
+        })
+        .collect()
+}
+
+fn quoted(tag: &str) -> String {
+    if tag == "*" { tag.to_string() } else { format!("\"{}\"", tag.trim_matches('"')) }
+}
+
+/// If-None-Match compares weakly, so `W/"abc"` matches the ETag `"abc"`.
+pub fn if_none_match_matches(header: &str, etag: &str) -> bool {
+    entity_tags(header).iter().any(|(tag, _)| tag == "*" || *tag == quoted(etag))
+}
+
+/// If-Match compares strongly, so weak tags never match.
+pub fn if_match_matches(header: &str, etag: &str) -> bool {
+    entity_tags(header).iter().any(|(tag, weak)| tag == "*" || (!weak && *tag == quoted(etag)))
+}
+
+/// Like if_match_matches, but the ETag of a variant of the object, see
+/// get_object_variant_as_string_if_modified, also matches, since clients may only have read the
+/// object through one, like the calendar entries for a query.
+pub fn if_match_matches_object_or_variant(header: &str, etag: &str) -> bool {
+    entity_tags(header).iter().any(|(tag, weak)| {
+        tag == "*" || (!weak && (*tag == quoted(etag) || variant_source(tag).as_ref() == Some(&quoted(etag))))
+    })
+}
+
+// Variants add a - and a hash of 16 hex digits to the ETag they were made from.
+fn variant_source(tag: &str) -> Option<String> {
+    let (source, hash) = tag.trim_matches('"').rsplit_once('-')?;
+    if hash.len() == 16 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) { Some(quoted(source)) } else { None }
+}
+
+/// The tags in the header that end with the suffix, with the suffix removed.
+pub fn tags_with_suffix(header: &str, suffix: &str) -> Vec<String> {
+    entity_tags(header).into_iter()
+        .filter_map(|(tag, _)| tag.trim_matches('"').strip_suffix(suffix).map(quoted))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_test() {
+        assert!(if_none_match_matches("\"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("W/\"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("abc", "\"abc\""));
+        assert!(if_none_match_matches("\"def\", W/\"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("*", "\"abc\""));
+        assert!(!if_none_match_matches("\"abcd\"", "\"abc\""));
+        assert!(!if_none_match_matches("", "\"abc\""));
+    }
+
+    #[test]
+    fn if_match_test() {
+        assert!(if_match_matches("\"abc\"", "\"abc\""));
+        assert!(if_match_matches("abc", "\"abc\""));
+        assert!(if_match_matches("*", "\"abc\""));
+        assert!(!if_match_matches("W/\"abc\"", "\"abc\""));
+        assert!(!if_match_matches("\"def\"", "\"abc\""));
+
+        assert!(if_match_matches_object_or_variant("\"abc-2-00000000075bcd15\"", "\"abc-2\""));
+        assert!(if_match_matches_object_or_variant("\"abc-2\"", "\"abc-2\""));
+        assert!(!if_match_matches_object_or_variant("\"abc-2\"", "\"abc\""));
+        assert!(!if_match_matches_object_or_variant("W/\"abc-2-00000000075bcd15\"", "\"abc-2\""));
+        assert!(!if_match_matches_object_or_variant("\"def-00000000075bcd15\"", "\"abc\""));
+    }
+
+    #[test]
+    fn header_test() {
+        assert_eq!(vec!["\"abc-2\"".to_string()], tags_with_suffix("W/\"abc-2-0f\", \"def\"", "-0f"));
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::{ApiGatewayRequest, Header, Response};
+
+/// Payload format 2.0, which HTTP APIs and Lambda function URLs send:
+/// {
+///     "version": "2.0",
+///     "rawPath": "/get-all-calendar-entries",
+///     "cookies": ["foo=bar"],
+///     "headers": {"origin": "foo"},
+///     "queryStringParameters": {"tag": "jobb,ferie"},
+///     "requestContext": {"http": {"method": "GET"}, "stage": "$default"},
+///     "isBase64Encoded": false
+/// }
+#[derive(Serialize, Deserialize)]
+pub struct ApiGatewayV2Request {
+    pub version: String,
+    #[serde(rename = "rawPath")]
+    pub raw_path: String,
+    #[serde(default)]
+    pub cookies: Vec<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // repeated parameters come joined with commas.
+    #[serde(rename = "queryStringParameters")]
+    pub query_string_parameters: Option<HashMap<String, String>>,
+    #[serde(rename = "requestContext")]
+    pub request_context: RequestContext,
+    pub body: Option<String>,
+    #[serde(rename = "isBase64Encoded", default)]
+    pub is_base64_encoded: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RequestContext {
+    pub http: HttpContext,
+    pub stage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HttpContext {
+    pub method: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiGatewayV2Response {
+    #[serde(rename = "statusCode")]
+    pub status_code: u32,
+    pub headers: HashMap<Header, String>,
+    pub body: String,
+    #[serde(rename = "isBase64Encoded")]
+    pub is_base64_encoded: bool,
+}
+
+/// Fails with a message for the client if the body isn't base64 encoded UTF-8 when it says it is.
+impl TryFrom<ApiGatewayV2Request> for ApiGatewayRequest {
+    type Error = String;
+
+    fn try_from(request: ApiGatewayV2Request) -> Result<ApiGatewayRequest, String> {
+        // rawPath starts with the stage, unless it's the default stage that function URLs always use.
+        let path = match request.request_context.stage.as_deref() {
+            Some(stage) if stage != "$default" => {
+                let stage_prefix = format!("/{}", stage);
+                match request.raw_path.strip_prefix(&stage_prefix) {
+                    Some(path) if path.is_empty() || path.starts_with('/') => path.to_string(),
+                    _ => request.raw_path.clone()
+                }
+            },
+            _ => request.raw_path.clone()
+        };
+
+        let mut headers = request.headers;
+        if !request.cookies.is_empty() {
+            headers.insert("cookie".to_string(), request.cookies.join("; "));
+        }
+
+        // function URLs base64 encode bodies that aren't text, like .ics files sent as application/octet-stream.
+        let body = match request.body {
+            Some(body) if request.is_base64_encoded => {
+                let bytes = base64::decode(body).map_err(|error| format!("Invalid base64 body: {}", error))?;
+                Some(String::from_utf8(bytes).map_err(|error| format!("The body isn't UTF-8: {}", error))?)
+            },
+            body => body
+        };
+
+        Ok(ApiGatewayRequest {
+            body,
+            headers,
+            http_method: request.request_context.http.method,
+            path,
+            query_string_parameters: request.query_string_parameters,
+            multi_value_query_string_parameters: None,
+        })
+    }
+}
+
+impl From<Response> for ApiGatewayV2Response {
+    fn from(response: Response) -> ApiGatewayV2Response {
+        ApiGatewayV2Response { status_code: response.status_code, headers: response.headers, body: response.body, is_base64_encoded: false }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: &str) -> ApiGatewayRequest {
+        ApiGatewayRequest::try_from(serde_json::from_str::<ApiGatewayV2Request>(json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn http_api_request_test() {
+        let request = request(r#"{
+            "version": "2.0",
+            "rawPath": "/prod/get-all-calendar-entries",
+            "rawQueryString": "tag=jobb&tag=ferie",
+            "cookies": ["a=1", "b=2"],
+            "headers": {"origin": "https://example.com"},
+            "queryStringParameters": {"tag": "jobb,ferie"},
+            "requestContext": {"http": {"method": "GET", "path": "/prod/get-all-calendar-entries"}, "stage": "prod"},
+            "isBase64Encoded": false
+        }"#);
+
+        assert_eq!("GET", request.http_method);
+        assert_eq!("/get-all-calendar-entries", request.path);
+        assert_eq!(Some(&"a=1; b=2".to_string()), request.headers.get("cookie"));
+        assert_eq!(Some(&"https://example.com".to_string()), request.headers.get("origin"));
+        assert_eq!(Some("jobb,ferie"), request.query_parameters().get("tag").map(|tag| tag.as_str()));
+        assert_eq!(None, request.body);
+    }
+
+    #[test]
+    fn function_url_request_test() {
+        let request = request(r#"{
+            "version": "2.0",
+            "rawPath": "/import-ics",
+            "rawQueryString": "",
+            "headers": {"content-type": "application/octet-stream"},
+            "requestContext": {"http": {"method": "POST"}, "stage": "$default"},
+            "body": "QkVHSU46VkNBTEVOREFS",
+            "isBase64Encoded": true
+        }"#);
+
+        assert_eq!("POST", request.http_method);
+        assert_eq!("/import-ics", request.path);
+        assert!(request.query_parameters().is_empty());
+        assert_eq!(Some("BEGIN:VCALENDAR".to_string()), request.body);
+    }
+
+    #[test]
+    fn undecodable_body_test() {
+        let request = |body: &str| serde_json::from_str::<ApiGatewayV2Request>(&format!(r#"{{
+            "version": "2.0",
+            "rawPath": "/import-ics",
+            "requestContext": {{"http": {{"method": "POST"}}, "stage": "$default"}},
+            "body": "{}",
+            "isBase64Encoded": true
+        }}"#, body)).unwrap();
+
+        assert!(ApiGatewayRequest::try_from(request("not base64!")).is_err());
+        // base64 of the Latin-1 bytes of "Tur på fjellet".
+        assert!(ApiGatewayRequest::try_from(request("VHVyIHDlIGZqZWxsZXQ=")).is_err());
+    }
+}
@@ -0,0 +1,25 @@
+use chrono::prelude::*;
+
+/// Formats a time like `Tue, 15 Jun 2021 08:30:00 GMT`, for Last-Modified.
+pub fn format_http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Reads an If-Modified-Since time. None for dates that aren't valid, which the header should then be ignored for.
+pub fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date.trim()).ok().map(|time| time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_date_test() {
+        let time = Utc.from_utc_datetime(&NaiveDate::from_ymd_opt(2021, 6, 5).unwrap().and_hms_opt(8, 30, 0).unwrap());
+        assert_eq!("Sat, 05 Jun 2021 08:30:00 GMT", format_http_date(time));
+        assert_eq!(Some(time), parse_http_date(&format_http_date(time)));
+        assert_eq!(Some(time), parse_http_date("Sat, 5 Jun 2021 10:30:00 +0200"));
+        assert_eq!(None, parse_http_date("yesterday"));
+    }
+}
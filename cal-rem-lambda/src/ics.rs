@@ -0,0 +1,696 @@
+use chrono::{prelude::*, Duration};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use lazy_static::lazy_static;
+use regex::Regex;
+use cal_rem_shared::{Entry, Frequency, HourMinute, Recurrence, RecurrenceLimit, YearMonthDate, local_date_time, num_to_month};
+use crate::editor::check_writable;
+
+/// Writes entries as an RFC 5545 calendar. Entries without a known date, or with dates that
+/// don't exist, are left out. Recurring entries become a single VEVENT with an RRULE.
+pub fn calendar_to_ics(entries: &[Entry], home_time_zone: Tz, now: DateTime<Utc>) -> String {
+    let events: Vec<(&Entry, Tz, Vec<String>)> = entries.iter()
+        .filter_map(|entry| event(entry, home_time_zone, now).map(|(time_zone, lines)| (entry, time_zone, lines)))
+        .collect();
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//cal-rem//calendar.txt//NO".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+
+    // every zone a timed event refers to, with the rules from the first year it's used in.
+    let mut time_zones: Vec<(Tz, i32)> = vec![];
+    for (entry, time_zone, _) in events.iter().filter(|(entry, _, _)| entry.start_time.is_some()) {
+        match time_zones.iter_mut().find(|(zone, _)| zone == time_zone) {
+            Some((_, year)) => *year = (*year).min(entry.year as i32),
+            None => time_zones.push((*time_zone, entry.year as i32))
+        }
+    }
+    time_zones.sort_by_key(|(time_zone, _)| time_zone.name());
+
+    for (time_zone, year) in time_zones {
+        lines.extend(vtimezone(time_zone, year));
+    }
+
+    for (_, _, event) in events {
+        lines.extend(event);
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect::<Vec<String>>().join("\r\n") + "\r\n"
+}
+
+fn is_in_ics(entry: &Entry, home_time_zone: Tz) -> bool {
+    entry.start_date.is_some() && entry.get_date_time(home_time_zone).is_ok() && entry.get_end_date_time(home_time_zone).is_ok()
+}
+
+fn event(entry: &Entry, home_time_zone: Tz, now: DateTime<Utc>) -> Option<(Tz, Vec<String>)> {
+    if !is_in_ics(entry, home_time_zone) {
+        return None;
+    }
+
+    let time_zone = entry.time_zone(home_time_zone);
+    let start = entry.start_naive_date()?;
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@cal-rem", entry.id),
+        format!("DTSTAMP:{}", now.format("%Y%m%dT%H%M%SZ")),
+    ];
+
+    match entry.start_time {
+        Some(_) => {
+            let tz_id = time_zone.name();
+            lines.push(format!("DTSTART;TZID={}:{}", tz_id, entry.get_date_time(home_time_zone).ok()?.naive_local().format("%Y%m%dT%H%M%S")));
+            if entry.end_time.is_some() || entry.end_date.is_some() {
+                // get_end_date_time ignores the end time of entries on a single day.
+                let end_date = entry.end_naive_date().unwrap_or(start);
+                let end = local_date_time(time_zone, end_date, entry.end_time.unwrap_or(HourMinute { hour: 23, minute: 59 })).ok()?;
+                lines.push(format!("DTEND;TZID={}:{}", tz_id, end.naive_local().format("%Y%m%dT%H%M%S")));
+            }
+        },
+        None => {
+            // all day events end the day after their last day.
+            let end = entry.end_naive_date().unwrap_or(start) + Duration::days(1);
+            lines.push(format!("DTSTART;VALUE=DATE:{}", start.format("%Y%m%d")));
+            lines.push(format!("DTEND;VALUE=DATE:{}", end.format("%Y%m%d")));
+        }
+    }
+
+    if let Some(recurrence) = &entry.recurrence {
+        lines.push(format!("RRULE:{}", rrule(recurrence, entry.start_time.is_some(), time_zone)));
+    }
+
+    lines.push(format!("SUMMARY:{}", escape_text(&entry.description)));
+
+    if let Some(location) = &entry.location {
+        lines.push(format!("LOCATION:{}", escape_text(location)));
+    }
+
+    if !entry.tags.is_empty() {
+        lines.push(format!("CATEGORIES:{}", entry.tags.iter().map(|tag| escape_text(tag)).collect::<Vec<String>>().join(",")));
+    }
+
+    if entry.uncertain_date {
+        lines.push("STATUS:TENTATIVE".to_string());
+    }
+
+    lines.push("END:VEVENT".to_string());
+    Some((time_zone, lines))
+}
+
+fn rrule(recurrence: &Recurrence, timed: bool, time_zone: Tz) -> String {
+    let mut rule = match recurrence.frequency {
+        Frequency::Weekly { interval } if interval > 1 => format!("FREQ=WEEKLY;INTERVAL={}", interval),
+        Frequency::Weekly { .. } => "FREQ=WEEKLY".to_string(),
+        Frequency::Monthly => "FREQ=MONTHLY".to_string(),
+        Frequency::Yearly => "FREQ=YEARLY".to_string(),
+    };
+
+    match recurrence.limit {
+        Some(RecurrenceLimit::Count(count)) => rule.push_str(&format!(";COUNT={}", count)),
+        Some(RecurrenceLimit::Until(until)) => {
+            if let Some(until) = until.to_naive_date() {
+                // UNTIL has to be in UTC when the event has a time zone.
+                let until = if timed {
+                    local_date_time(time_zone, until, HourMinute { hour: 23, minute: 59 })
+                        .map(|until| until.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string())
+                        .unwrap_or_else(|_| until.format("%Y%m%d").to_string())
+                } else {
+                    until.format("%Y%m%d").to_string()
+                };
+                rule.push_str(&format!(";UNTIL={}", until));
+            }
+        },
+        None => {}
+    }
+
+    rule
+}
+
+// A VTIMEZONE with the daylight saving changes of `year`, repeating yearly on the same weekday
+// of the month, which is how the European and American rules are written.
+fn vtimezone(time_zone: Tz, year: i32) -> Vec<String> {
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", time_zone.name())];
+    let transitions = transitions(time_zone, year);
+
+    if transitions.is_empty() {
+        let offset = time_zone.offset_from_utc_datetime(&NaiveDate::from_ymd_opt(year, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        let utc_offset = format_offset(offset.fix().local_minus_utc());
+        lines.extend(vec![
+            "BEGIN:STANDARD".to_string(),
+            "DTSTART:19700101T000000".to_string(),
+            format!("TZOFFSETFROM:{}", utc_offset),
+            format!("TZOFFSETTO:{}", utc_offset),
+            format!("TZNAME:{}", offset.abbreviation()),
+            "END:STANDARD".to_string(),
+        ]);
+    }
+
+    for (onset, offset_from, offset_to) in transitions {
+        let kind = if offset_to.dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+        let week = if onset.day() + 7 > days_in_month(onset.date()) { -1 } else { (onset.day() as i32 - 1) / 7 + 1 };
+
+        lines.extend(vec![
+            format!("BEGIN:{}", kind),
+            format!("DTSTART:{}", onset.format("%Y%m%dT%H%M%S")),
+            format!("RRULE:FREQ=YEARLY;BYMONTH={};BYDAY={}{}", onset.month(), week, weekday_code(onset.weekday())),
+            format!("TZOFFSETFROM:{}", format_offset(offset_from)),
+            format!("TZOFFSETTO:{}", format_offset(offset_to.fix().local_minus_utc())),
+            format!("TZNAME:{}", offset_to.abbreviation()),
+            format!("END:{}", kind),
+        ]);
+    }
+
+    lines.push("END:VTIMEZONE".to_string());
+    lines
+}
+
+// The local time each offset change in `year` happens at, read with the offset from before
+// it, together with the offsets before and after.
+fn transitions(time_zone: Tz, year: i32) -> Vec<(NaiveDateTime, i32, <Tz as TimeZone>::Offset)> {
+    let offset_at = |utc: NaiveDateTime| time_zone.offset_from_utc_datetime(&utc).fix().local_minus_utc();
+    let mut transitions = vec![];
+    let mut hour = NaiveDate::from_ymd_opt(year, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+
+    while hour.year() == year {
+        let next_hour = hour + Duration::hours(1);
+        let offset_before = offset_at(hour);
+
+        if offset_before != offset_at(next_hour) {
+            // the first minute with the new offset.
+            let (mut low, mut high) = (0, 60);
+            while high - low > 1 {
+                let middle = (low + high) / 2;
+                if offset_at(hour + Duration::minutes(middle)) == offset_before { low = middle } else { high = middle }
+            }
+
+            let change = hour + Duration::minutes(high);
+            transitions.push((change + Duration::seconds(offset_before as i64), offset_before, time_zone.offset_from_utc_datetime(&change)));
+        }
+
+        hour = next_hour;
+    }
+
+    transitions
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1).unwrap().pred_opt().unwrap().day()
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { "-" } else { "+" };
+    format!("{}{:02}{:02}", sign, seconds.abs() / 3600, seconds.abs() % 3600 / 60)
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+// Lines longer than 75 octets continue on the next line after a space, without splitting a character.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+
+    for character in line.chars() {
+        if length + character.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(character);
+        length += character.len_utf8();
+    }
+
+    folded
+}
+
+/// Reads the VEVENTs of an .ics file as entries, with a warning for each thing that couldn't
+/// be kept, like exceptions to a recurrence, alarms or rules calendar.txt can't express.
+/// Times are read in `home_time_zone` unless the event says otherwise.
+pub fn ics_to_entries(ics: &str, home_time_zone: Tz) -> (Vec<Entry>, Vec<String>) {
+    let mut entries = vec![];
+    let mut warnings = vec![];
+    let mut event: Option<Vec<Property>> = None;
+    let mut nested = 0;
+
+    for property in unfold(ics).iter().filter_map(|line| property(line)) {
+        match (property.name.as_str(), property.value.to_uppercase().as_str(), &mut event) {
+            ("BEGIN", "VEVENT", None) => event = Some(vec![]),
+            ("END", "VEVENT", Some(_)) if nested == 0 => {
+                let properties = event.take().unwrap();
+                let summary = summary(&properties);
+                match event_to_entry(&properties, home_time_zone, &mut warnings) {
+                    Ok(entry) => entries.push(entry),
+                    Err(reason) => warnings.push(format!("\"{}\" was not imported: {}", summary, reason))
+                }
+            },
+            ("BEGIN", component, Some(properties)) => {
+                // alarms and other components inside an event are left out.
+                if nested == 0 {
+                    properties.push(Property { name: format!("BEGIN:{}", component), params: vec![], value: "".to_string() });
+                }
+                nested += 1;
+            },
+            ("END", _, Some(_)) => nested -= 1,
+            (_, _, Some(properties)) if nested == 0 => properties.push(property),
+            _ => {}
+        }
+    }
+
+    (entries, warnings)
+}
+
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+}
+
+enum IcsTime {
+    Date(NaiveDate),
+    Utc(NaiveDateTime),
+    Local(NaiveDateTime, Option<String>),
+}
+
+fn event_to_entry(properties: &[Property], home_time_zone: Tz, warnings: &mut Vec<String>) -> Result<Entry, String> {
+    let get = |name: &str| properties.iter().find(|property| property.name == name);
+    let summary = summary(properties);
+    let mut warn = |message: String| warnings.push(format!("\"{}\": {}", summary, message));
+
+    if get("RECURRENCE-ID").is_some() {
+        return Err("it changes a single occurrence of a recurring event, which isn't supported".to_string());
+    }
+    if get("STATUS").map_or(false, |status| status.value.eq_ignore_ascii_case("CANCELLED")) {
+        return Err("it is cancelled".to_string());
+    }
+
+    let start = get("DTSTART").and_then(ics_time).ok_or("it has no start date")?;
+    let (start_date, start, time_zone) = resolve(start, None, home_time_zone, &mut warn);
+
+    let end = match (get("DTEND").and_then(ics_time), get("DURATION").and_then(|duration| parse_duration(&duration.value))) {
+        (Some(end), _) => Some(resolve(end, Some(time_zone), home_time_zone, &mut warn)),
+        (None, Some(duration)) => Some(match start {
+            Some(start) => ((start + duration).date(), Some(start + duration), time_zone),
+            None => (start_date + duration, None, time_zone),
+        }),
+        (None, None) => None
+    };
+
+    let mut entry = Entry {
+        location: get("LOCATION").map(|location| sanitize(&unescape_text(&location.value), &['[', ']', '{', '}'])).filter(|location| !location.is_empty()),
+        uncertain_date: get("STATUS").map_or(false, |status| status.value.eq_ignore_ascii_case("TENTATIVE")),
+        start_time: start.map(|start| HourMinute { hour: start.hour(), minute: start.minute() }),
+        time_zone: if time_zone != home_time_zone { Some(time_zone.name().to_string()) } else { None },
+        tags: properties.iter().filter(|property| property.name == "CATEGORIES").flat_map(|property| tags(&property.value)).collect(),
+        ..Entry::on(start_date.year() as u32, num_to_month(start_date.month()).unwrap(), Some(start_date.day()), &sanitize(&summary, &['@', '[', ']', '{', '}', '#']))
+    };
+
+    if let Some((end_date, end, _)) = end {
+        // all day events end the day after their last day.
+        let last_date = if end.is_none() { end_date - Duration::days(1) } else { end_date };
+
+        if last_date > start_date {
+            entry.end_date = Some(last_date.day());
+            entry.end_month = num_to_month(last_date.month());
+            entry.end_year = Some(last_date.year() as u32);
+        }
+        if let (Some(start), Some(end)) = (start, end) {
+            if end > start {
+                entry.end_time = Some(HourMinute { hour: end.hour(), minute: end.minute() });
+            }
+        }
+    }
+
+    if let Some(rule) = get("RRULE") {
+        match recurrence(&rule.value, start_date, time_zone) {
+            Some(recurrence) => entry.recurrence = Some(recurrence),
+            None => warn(format!("the recurrence {} is not supported, so only the first occurrence was imported", rule.value))
+        }
+    }
+
+    for name in ["EXDATE", "RDATE"] {
+        if get(name).is_some() {
+            warn(format!("{} is not supported, so the recurrence has no exceptions or extra dates", name));
+        }
+    }
+    if get("BEGIN:VALARM").is_some() {
+        warn("alarms are not imported, the entry gets the default reminders".to_string());
+    }
+
+    check_writable(&entry)?;
+    Ok(entry)
+}
+
+fn summary(properties: &[Property]) -> String {
+    properties.iter().find(|property| property.name == "SUMMARY")
+        .map(|summary| unescape_text(&summary.value))
+        .filter(|summary| !summary.is_empty())
+        .unwrap_or_else(|| "Uten tittel".to_string())
+}
+
+// The date, the local time if it has one, and the zone it's in. A time is converted to
+// `time_zone` if given, otherwise it stays in its own zone, with UTC and floating times read in the home zone.
+fn resolve(time: IcsTime, time_zone: Option<Tz>, home_time_zone: Tz, warn: &mut impl FnMut(String)) -> (NaiveDate, Option<NaiveDateTime>, Tz) {
+    match time {
+        IcsTime::Date(date) => (date, None, time_zone.unwrap_or(home_time_zone)),
+        IcsTime::Utc(utc) => {
+            let time_zone = time_zone.unwrap_or(home_time_zone);
+            let local = time_zone.from_utc_datetime(&utc).naive_local();
+            (local.date(), Some(local), time_zone)
+        },
+        IcsTime::Local(local, tz_id) => {
+            let own_time_zone = match tz_id {
+                Some(tz_id) => tz_id.trim_start_matches('/').parse::<Tz>().unwrap_or_else(|_| {
+                    warn(format!("the time zone {} is unknown, so its times were read in {}", tz_id, home_time_zone.name()));
+                    home_time_zone
+                }),
+                None => home_time_zone
+            };
+            let time_zone = time_zone.unwrap_or(own_time_zone);
+            let local = own_time_zone.from_local_datetime(&local).earliest()
+                .map_or(local, |date_time| date_time.with_timezone(&time_zone).naive_local());
+            (local.date(), Some(local), time_zone)
+        }
+    }
+}
+
+fn ics_time(property: &Property) -> Option<IcsTime> {
+    let value = property.value.trim();
+
+    if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+        NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(IcsTime::Date)
+    } else if let Some(utc) = value.strip_suffix('Z') {
+        NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok().map(IcsTime::Utc)
+    } else {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok().map(|local| IcsTime::Local(local, property.param("TZID").map(|tz_id| tz_id.to_string())))
+    }
+}
+
+// Durations like P1D, PT1H30M or P2W. Negative durations aren't used for events.
+fn parse_duration(value: &str) -> Option<Duration> {
+    lazy_static! {
+        static ref DURATION: Regex = Regex::new(r"^\+?P(?:(?P<weeks>\d+)W)?(?:(?P<days>\d+)D)?(?:T(?:(?P<hours>\d+)H)?(?:(?P<minutes>\d+)M)?(?:(?P<seconds>\d+)S)?)?$").unwrap();
+    }
+
+    let captures = DURATION.captures(value.trim())?;
+    let part = |name: &str| captures.name(name).map_or(0, |part| part.as_str().parse::<i64>().unwrap_or(0));
+    Some(Duration::weeks(part("weeks")) + Duration::days(part("days")) + Duration::hours(part("hours")) + Duration::minutes(part("minutes")) + Duration::seconds(part("seconds")))
+}
+
+// The RRULEs calendar.txt can express: weekly with an interval, monthly and yearly on the
+// start date, ending on a date or after a number of times.
+fn recurrence(rule: &str, start: NaiveDate, time_zone: Tz) -> Option<Recurrence> {
+    let mut frequency = None;
+    let mut interval = 1;
+    let mut limit = None;
+
+    for part in rule.split(';').filter(|part| !part.is_empty()) {
+        let (name, value) = part.split_once('=')?;
+        match name.to_uppercase().as_str() {
+            "FREQ" => frequency = Some(value.to_uppercase()),
+            "INTERVAL" => interval = value.parse().ok()?,
+            "COUNT" => limit = Some(RecurrenceLimit::Count(value.parse().ok()?)),
+            "UNTIL" => {
+                // an UNTIL in UTC is the end of the last day in the event's own zone.
+                let until = match value.strip_suffix('Z') {
+                    Some(utc) => time_zone.from_utc_datetime(&NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?).naive_local().date(),
+                    None => NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()?
+                };
+                limit = Some(RecurrenceLimit::Until(YearMonthDate { year: until.year() as u32, month: num_to_month(until.month())?, date: until.day() }));
+            },
+            "BYDAY" if value.eq_ignore_ascii_case(weekday_code(start.weekday())) => {},
+            "BYMONTHDAY" if value == start.day().to_string() => {},
+            "BYMONTH" if value == start.month().to_string() => {},
+            "WKST" => {},
+            _ => return None
+        }
+    }
+
+    let frequency = match (frequency?.as_str(), interval) {
+        ("WEEKLY", interval) => Frequency::Weekly { interval },
+        ("MONTHLY", 1) => Frequency::Monthly,
+        ("YEARLY", 1) => Frequency::Yearly,
+        _ => return None
+    };
+
+    Some(Recurrence { frequency, limit })
+}
+
+fn tags(categories: &str) -> Vec<String> {
+    categories.split(',')
+        .map(|category| unescape_text(category).to_lowercase().chars().filter(|character| character.is_alphanumeric() || *character == '_').collect::<String>())
+        .filter(|tag| tag.chars().next().map_or(false, |first| first.is_alphabetic()))
+        .collect()
+}
+
+fn sanitize(text: &str, characters: &[char]) -> String {
+    text.replace(characters, " ").split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut characters = text.chars();
+
+    while let Some(character) = characters.next() {
+        if character == '\\' {
+            match characters.next() {
+                Some('n') | Some('N') => unescaped.push(' '),
+                Some(escaped) => unescaped.push(escaped),
+                None => {}
+            }
+        } else {
+            unescaped.push(character);
+        }
+    }
+
+    unescaped.trim().to_string()
+}
+
+// Joins folded lines back together. Files from the wild sometimes use \n instead of \r\n.
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+
+    for line in ics.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)) {
+        match lines.last_mut() {
+            Some(last) if line.starts_with(' ') || line.starts_with('\t') => last.push_str(&line[1..]),
+            _ if line.is_empty() => {},
+            _ => lines.push(line.to_string())
+        }
+    }
+
+    lines
+}
+
+fn property(line: &str) -> Option<Property> {
+    // the value starts after the first colon that isn't in a quoted parameter.
+    let mut quoted = false;
+    let colon = line.char_indices().find(|(_, character)| {
+        if *character == '"' {
+            quoted = !quoted;
+        }
+        *character == ':' && !quoted
+    })?.0;
+
+    let mut parts = line[..colon].split(';');
+    let name = parts.next()?.trim().to_uppercase();
+    let params = parts.filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name.trim().to_uppercase(), value.trim().trim_matches('"').to_string()))
+        .collect();
+
+    Some(Property { name, params, value: line[colon + 1..].to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Oslo;
+    use cal_rem_shared::{Month, YearMonthDate};
+
+    fn entry(year: u32, month: Month, date: Option<u32>, description: &str) -> Entry {
+        let entry = Entry::on(year, month, date, description);
+        Entry { id: entry.content_id(), ..entry }
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.from_utc_datetime(&NaiveDate::from_ymd_opt(2021, 1, 2).unwrap().and_hms_opt(3, 4, 5).unwrap())
+    }
+
+    #[test]
+    fn event_test() {
+        let mut meeting = entry(2021, Month::June, Some(15), "Meeting, with; notes");
+        meeting.location = Some("Office".to_string());
+        meeting.start_time = Some(HourMinute { hour: 10, minute: 0 });
+        meeting.end_time = Some(HourMinute { hour: 11, minute: 30 });
+        meeting.tags = vec!["jobb".to_string()];
+        meeting.recurrence = Some(Recurrence { frequency: Frequency::Weekly { interval: 2 }, limit: Some(RecurrenceLimit::Until(YearMonthDate { year: 2021, month: Month::July, date: 31 })) });
+
+        let mut trip = entry(2021, Month::December, Some(28), "Trip");
+        trip.end_date = Some(3);
+        trip.end_month = Some(Month::January);
+        trip.end_year = Some(2022);
+        trip.uncertain_date = true;
+
+        let undated = entry(2021, Month::June, None, "Some time");
+        let invalid = entry(2021, Month::June, Some(31), "No such date");
+
+        let ics = calendar_to_ics(&[meeting.clone(), trip.clone(), undated, invalid], Oslo, now());
+        let expected = vec![
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "PRODID:-//cal-rem//calendar.txt//NO",
+            "CALSCALE:GREGORIAN",
+            "BEGIN:VTIMEZONE",
+            "TZID:Europe/Oslo",
+            "BEGIN:DAYLIGHT",
+            "DTSTART:20210328T020000",
+            "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU",
+            "TZOFFSETFROM:+0100",
+            "TZOFFSETTO:+0200",
+            "TZNAME:CEST",
+            "END:DAYLIGHT",
+            "BEGIN:STANDARD",
+            "DTSTART:20211031T030000",
+            "RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU",
+            "TZOFFSETFROM:+0200",
+            "TZOFFSETTO:+0100",
+            "TZNAME:CET",
+            "END:STANDARD",
+            "END:VTIMEZONE",
+            "BEGIN:VEVENT",
+            &format!("UID:{}@cal-rem", meeting.id),
+            "DTSTAMP:20210102T030405Z",
+            "DTSTART;TZID=Europe/Oslo:20210615T100000",
+            "DTEND;TZID=Europe/Oslo:20210615T113000",
+            "RRULE:FREQ=WEEKLY;INTERVAL=2;UNTIL=20210731T215900Z",
+            "SUMMARY:Meeting\\, with\\; notes",
+            "LOCATION:Office",
+            "CATEGORIES:jobb",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            &format!("UID:{}@cal-rem", trip.id),
+            "DTSTAMP:20210102T030405Z",
+            "DTSTART;VALUE=DATE:20211228",
+            "DTEND;VALUE=DATE:20220104",
+            "SUMMARY:Trip",
+            "STATUS:TENTATIVE",
+            "END:VEVENT",
+            "END:VCALENDAR",
+            "",
+        ].join("\r\n");
+        assert_eq!(expected, ics);
+    }
+
+    #[test]
+    fn import_test() {
+        let ics = [
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "UID:1",
+            "SUMMARY:Planning\\, part 1 @ noon",
+            "LOCATION:Room 1",
+            "DTSTART:20210615T080000Z",
+            "DTEND:20210615T093000Z",
+            "CATEGORIES:Work,2nd",
+            "RRULE:FREQ=WEEKLY;BYDAY=TU;COUNT=3",
+            "BEGIN:VALARM",
+            "TRIGGER:-PT15M",
+            "END:VALARM",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Conference in Lon",
+            " don",
+            "DTSTART;TZID=\"Europe/London\":20211230T090000",
+            "DURATION:P3DT8H",
+            "RRULE:FREQ=MONTHLY;BYDAY=1MO",
+            "EXDATE:20220103T090000",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Holiday",
+            "DTSTART;VALUE=DATE:20210701",
+            "DTEND;VALUE=DATE:20210702",
+            "STATUS:TENTATIVE",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Moved occurrence",
+            "RECURRENCE-ID:20210622T080000Z",
+            "DTSTART:20210623T080000Z",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ].join("\r\n");
+
+        let (entries, warnings) = ics_to_entries(&ics, Oslo);
+
+        let mut planning = entry(2021, Month::June, Some(15), "Planning, part 1 noon");
+        planning.id = "".to_string();
+        planning.location = Some("Room 1".to_string());
+        planning.start_time = Some(HourMinute { hour: 10, minute: 0 });
+        planning.end_time = Some(HourMinute { hour: 11, minute: 30 });
+        planning.tags = vec!["work".to_string()];
+        planning.recurrence = Some(Recurrence { frequency: Frequency::Weekly { interval: 1 }, limit: Some(RecurrenceLimit::Count(3)) });
+
+        let mut conference = entry(2021, Month::December, Some(30), "Conference in London");
+        conference.id = "".to_string();
+        conference.start_time = Some(HourMinute { hour: 9, minute: 0 });
+        conference.end_date = Some(2);
+        conference.end_month = Some(Month::January);
+        conference.end_year = Some(2022);
+        conference.end_time = Some(HourMinute { hour: 17, minute: 0 });
+        conference.time_zone = Some("Europe/London".to_string());
+
+        let mut holiday = entry(2021, Month::July, Some(1), "Holiday");
+        holiday.id = "".to_string();
+        holiday.uncertain_date = true;
+
+        assert_eq!(vec![planning, conference, holiday], entries);
+        assert_eq!(vec![
+            "\"Planning, part 1 @ noon\": alarms are not imported, the entry gets the default reminders",
+            "\"Conference in London\": the recurrence FREQ=MONTHLY;BYDAY=1MO is not supported, so only the first occurrence was imported",
+            "\"Conference in London\": EXDATE is not supported, so the recurrence has no exceptions or extra dates",
+            "\"Moved occurrence\" was not imported: it changes a single occurrence of a recurring event, which isn't supported",
+        ], warnings);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut meeting = entry(2021, Month::June, Some(15), "Meeting");
+        meeting.id = "".to_string();
+        meeting.start_time = Some(HourMinute { hour: 10, minute: 0 });
+        meeting.end_time = Some(HourMinute { hour: 11, minute: 0 });
+        meeting.time_zone = Some("America/New_York".to_string());
+        meeting.recurrence = Some(Recurrence { frequency: Frequency::Yearly, limit: Some(RecurrenceLimit::Until(YearMonthDate { year: 2025, month: Month::June, date: 15 })) });
+
+        let (entries, warnings) = ics_to_entries(&calendar_to_ics(&[meeting.clone()], Oslo, now()), Oslo);
+        assert_eq!(vec![meeting], entries);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn folding_test() {
+        let line = format!("SUMMARY:{}", "å".repeat(40));
+        let folded = fold(&line);
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(line, folded.replace("\r\n ", ""));
+        assert_eq!("+0530", format_offset(5 * 3600 + 30 * 60));
+        assert_eq!("-0300", format_offset(-3 * 3600));
+    }
+}
@@ -0,0 +1,42 @@
+// Imports the events of an .ics file into a calendar.txt on disk, the same way POST /import-ics does:
+//
+//     import-ics calendar.txt invitation.ics [--dry-run]
+//
+// Times are read in CALENDAR_TIME_ZONE, which defaults to Europe/Oslo like in the lambda.
+use std::env::{args, var};
+use std::fs;
+use cal_rem_shared::{Settings, format_entry, month_name};
+use cal_rem_lambda::editor::merge_entries;
+use cal_rem_lambda::ics::ics_to_entries;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = args().skip(1).collect();
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    if paths.len() != 2 {
+        eprintln!("Usage: import-ics <calendar.txt> <file.ics> [--dry-run]");
+        std::process::exit(2);
+    }
+
+    let settings = Settings { time_zone: var("CALENDAR_TIME_ZONE").unwrap_or_else(|_| "Europe/Oslo".to_string()) };
+    let calendar = fs::read_to_string(paths[0])?;
+    let (entries, warnings) = ics_to_entries(&fs::read_to_string(paths[1])?, settings.home_time_zone());
+    let (calendar, imported, duplicates) = merge_entries(&calendar, entries);
+
+    for entry in &imported {
+        println!("Imported: {} {} {}", month_name(entry.month), entry.year, format_entry(entry));
+    }
+    for entry in &duplicates {
+        println!("Already in the calendar: {} {} {}", month_name(entry.month), entry.year, format_entry(entry));
+    }
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+
+    if !dry_run && !imported.is_empty() {
+        fs::write(paths[0], calendar)?;
+    }
+
+    Ok(())
+}
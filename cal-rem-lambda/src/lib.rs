@@ -0,0 +1,336 @@
+use lambda_runtime::{Context, Error};
+use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::auth::{Access, AuthConfig, AuthError, get_auth_config};
+use crate::todo::{TodoChange, change_todo_entries, get_todo_entries};
+use crate::cache::get_cache;
+use crate::calendar::{add_calendar_entry, delete_calendar_entry, get_calendar_events, get_calendar_ics, import_ics, update_calendar_entry, validate_calendar};
+use crate::http_api::{ApiGatewayV2Request, ApiGatewayV2Response};
+use crate::notifier::{get_messenger, run_notifier};
+use crate::router::{RouteMatch, Router};
+use crate::settings::get_settings_response;
+use crate::http_date::{format_http_date, parse_http_date};
+use crate::storage::{BrowserCachedData, ConditionalRequest, EditableData, Storage, get_storage};
+
+mod auth;
+pub mod cache;
+mod calendar;
+mod dynamodb;
+pub mod editor;
+mod etag;
+mod http_api;
+mod http_date;
+pub mod ics;
+mod matrix;
+pub mod notifier;
+mod notify;
+mod parser;
+mod query;
+mod router;
+mod s3;
+mod settings;
+pub mod storage;
+mod todo;
+
+/*
+ApiGateway Request:
+{
+    "body":"{\"command\":\"GET_CALENDAR_EVENTS\",\"parameters\":\"\"}",
+    "headers":{"Origin":"foo"},
+    "httpMethod":"POST"
+}
+
+HTTP API and Lambda function URL requests use payload format 2.0, see ApiGatewayV2Request.
+
+CloudWatch Event:
+{
+    "detail-type": "foo",
+    "source": "bar"
+}
+*/
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Event {
+    ApiGatewayRequest(ApiGatewayRequest),
+    ApiGatewayV2Request(ApiGatewayV2Request),
+    CloudWatchEvent(CloudWatchEvent),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CloudWatchEvent {
+    #[serde(rename = "detail-type")]
+    detail_type: String,
+    source: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiGatewayRequest {
+    pub body: Option<String>,
+    pub headers: HashMap<String, String>,
+    #[serde(rename = "httpMethod")]
+    pub http_method: String,
+    pub path: String,
+    #[serde(rename = "queryStringParameters")]
+    pub query_string_parameters: Option<HashMap<String, String>>,
+    #[serde(rename = "multiValueQueryStringParameters")]
+    pub multi_value_query_string_parameters: Option<HashMap<String, Vec<String>>>,
+}
+
+impl ApiGatewayRequest {
+    /// Header names are case-insensitive, and REST APIs pass them on as the client sent them.
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    /// The query parameters, with the values of repeated parameters joined with commas
+    /// like payload format 2.0 does.
+    pub fn query_parameters(&self) -> HashMap<String, String> {
+        match &self.multi_value_query_string_parameters {
+            Some(parameters) => parameters.iter().map(|(name, values)| (name.clone(), values.join(","))).collect(),
+            None => self.query_string_parameters.clone().unwrap_or_default()
+        }
+    }
+}
+
+/// Answers in the payload format the request came in.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EventResponse {
+    Response(Response),
+    ApiGatewayV2Response(ApiGatewayV2Response),
+}
+
+#[derive(Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub enum Header {
+    #[serde(rename = "Access-Control-Allow-Headers")]
+    AccessControlAllowHeaders,
+    #[serde(rename = "Access-Control-Allow-Methods")]
+    AccessControlAllowMethods,
+    #[serde(rename = "Access-Control-Allow-Origin")]
+    AccessControlAllowOrigin,
+    #[serde(rename = "Access-Control-Expose-Headers")]
+    AccessControlExposeHeaders,
+    Allow,
+    #[serde(rename = "Cache-Control")]
+    CacheControl,
+    #[serde(rename = "Content-Type")]
+    ContentType,
+    ETag,
+    Expires,
+    #[serde(rename = "Last-Modified")]
+    LastModified,
+    Vary,
+    #[serde(rename = "WWW-Authenticate")]
+    WwwAuthenticate,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Response {
+    #[serde(rename = "statusCode")]
+    pub status_code: u32,
+    pub headers: HashMap<Header, String>,
+    pub body: String,
+}
+
+pub async fn my_handler(event: Event, _ctx: Context) -> Result<EventResponse, Error> {
+    return match event {
+        Event::CloudWatchEvent(_cloud_watch_event) => {
+            run_notifier(get_storage()?.as_ref(), get_cache().as_ref(), get_messenger().as_ref()).await?;
+            Ok(EventResponse::Response(Response { status_code: 200, headers: get_default_headers(), body: "".to_string()}))
+        },
+        Event::ApiGatewayRequest(api_gateway_request) => {
+            Ok(EventResponse::Response(handle_request(api_gateway_request, get_storage()?.as_ref()).await?))
+        },
+        Event::ApiGatewayV2Request(api_gateway_v2_request) => {
+            let origin = api_gateway_v2_request.headers.get("origin").cloned();
+            let response = match ApiGatewayRequest::try_from(api_gateway_v2_request) {
+                Ok(api_gateway_request) => handle_request(api_gateway_request, get_storage()?.as_ref()).await?,
+                Err(message) => {
+                    let response = Response { status_code: 400, headers: get_default_headers(), body: message };
+                    with_cors_headers(response, &get_auth_config(), origin.as_deref())
+                }
+            };
+            Ok(EventResponse::ApiGatewayV2Response(response.into()))
+        }
+    }
+}
+
+/// Answers a request to the API, whichever way it came in.
+pub async fn handle_request(api_gateway_request: ApiGatewayRequest, storage: &dyn Storage) -> Result<Response, Error> {
+    let auth_config = get_auth_config();
+    let origin = api_gateway_request.header("origin").cloned();
+    let response = handle_api_gateway_request(api_gateway_request, &auth_config, storage).await?;
+
+    Ok(with_cors_headers(response, &auth_config, origin.as_deref()))
+}
+
+// Allows the request's origin to read the response if it's one of the allowed origins.
+fn with_cors_headers(mut response: Response, auth_config: &AuthConfig, origin: Option<&str>) -> Response {
+    let origin = auth_config.allowed_origin(origin);
+    response.headers.remove(&Header::AccessControlAllowOrigin);
+    // with an allowlist the response depends on the origin, also for origins that aren't on it, so
+    // caches mustn't give one origin's response to another.
+    if auth_config.allowed_origins.is_some() {
+        response.headers.insert(Header::Vary, "Origin".to_string());
+    }
+    if let Some(origin) = origin {
+        response.headers.insert(Header::AccessControlAllowOrigin, origin);
+    }
+
+    response
+}
+
+#[derive(Clone, Copy)]
+enum Endpoint {
+    CalendarEntries,
+    CalendarIcs,
+    TodoEntries,
+    ValidateCalendar,
+    Settings,
+    AddCalendarEntry,
+    UpdateCalendarEntry,
+    DeleteCalendarEntry,
+    ImportIcs,
+    ChangeTodoEntries(TodoChange),
+}
+
+fn api_router() -> Router<(Endpoint, Access)> {
+    Router::new()
+        .route("GET", "/get-all-calendar-entries", (Endpoint::CalendarEntries, Access::Read))
+        .route("GET", "/calendar.ics", (Endpoint::CalendarIcs, Access::Read))
+        .route("GET", "/get-all-todo-entries", (Endpoint::TodoEntries, Access::Read))
+        .route("GET", "/validate-calendar", (Endpoint::ValidateCalendar, Access::Read))
+        .route("GET", "/get-settings", (Endpoint::Settings, Access::Read))
+        .route("POST", "/calendar-entries", (Endpoint::AddCalendarEntry, Access::Write))
+        .route("PUT", "/calendar-entries/{id}", (Endpoint::UpdateCalendarEntry, Access::Write))
+        .route("DELETE", "/calendar-entries/{id}", (Endpoint::DeleteCalendarEntry, Access::Write))
+        .route("POST", "/import-ics", (Endpoint::ImportIcs, Access::Write))
+        .route("POST", "/todo-entries", (Endpoint::ChangeTodoEntries(TodoChange::Add), Access::Write))
+        .route("POST", "/todo-entries/complete", (Endpoint::ChangeTodoEntries(TodoChange::Complete), Access::Write))
+        .route("POST", "/todo-entries/reopen", (Endpoint::ChangeTodoEntries(TodoChange::Reopen), Access::Write))
+        .route("PUT", "/todo-entries/order", (Endpoint::ChangeTodoEntries(TodoChange::Reorder), Access::Write))
+}
+
+async fn handle_api_gateway_request(api_gateway_request: ApiGatewayRequest, auth_config: &AuthConfig, storage: &dyn Storage) -> Result<Response, Error> {
+    let router = api_router();
+    let allowed_methods = router.allowed_methods(&api_gateway_request.path).join(",");
+    let method = api_gateway_request.http_method.to_uppercase();
+
+    let mut response = match router.find(&method, &api_gateway_request.path) {
+        // CORS preflight, answered without an API key since browsers don't send one.
+        _ if method == "OPTIONS" && !allowed_methods.is_empty() => {
+            let mut headers = get_default_headers();
+            headers.insert(Header::Allow, allowed_methods.clone());
+            Response { status_code: 204, headers, body: "".to_string()}
+        },
+        RouteMatch::Found((endpoint, access), parameters) => {
+            match authorization_error_response(&api_gateway_request, auth_config, access) {
+                Some(response) => response,
+                None => handle_endpoint(endpoint, &parameters, api_gateway_request, storage).await?
+            }
+        },
+        RouteMatch::MethodNotAllowed(methods) => {
+            let mut headers = get_default_headers();
+            headers.insert(Header::Allow, methods.join(","));
+            Response { status_code: 405, headers, body: "Method not allowed".to_string()}
+        },
+        RouteMatch::NotFound => {
+            Response { status_code: 404, headers: get_default_headers(), body: "Resource not found".to_string()}
+        }
+    };
+
+    if !allowed_methods.is_empty() {
+        response.headers.insert(Header::AccessControlAllowMethods, allowed_methods);
+    }
+    // HEAD gets the headers of the GET response, without its body.
+    if method == "HEAD" {
+        response.body = "".to_string();
+    }
+
+    Ok(response)
+}
+
+fn authorization_error_response(api_gateway_request: &ApiGatewayRequest, auth_config: &AuthConfig, access: Access) -> Option<Response> {
+    let authorization = api_gateway_request.header("authorization").map(|authorization| authorization.as_str());
+    match auth_config.authorize(authorization, access) {
+        Err(AuthError::Unauthorized) => {
+            let mut headers = get_default_headers();
+            headers.insert(Header::WwwAuthenticate, "Bearer".to_string());
+            Some(Response { status_code: 401, headers, body: "Missing or unknown API key".to_string()})
+        },
+        Err(AuthError::Forbidden) => {
+            Some(Response { status_code: 403, headers: get_default_headers(), body: "The API key may only read".to_string()})
+        },
+        Ok(()) => None
+    }
+}
+
+async fn handle_endpoint(endpoint: Endpoint, parameters: &HashMap<String, String>, api_gateway_request: ApiGatewayRequest, storage: &dyn Storage) -> Result<Response, Error> {
+    let conditions = ConditionalRequest {
+        if_none_match: api_gateway_request.header("if-none-match").cloned(),
+        if_modified_since: api_gateway_request.header("if-modified-since").and_then(|date| parse_http_date(date)),
+    };
+    let if_match = api_gateway_request.header("if-match").cloned();
+    let query = api_gateway_request.query_parameters();
+    let body = api_gateway_request.body;
+    let id = parameters.get("id").map(|id| id.as_str()).unwrap_or_default();
+
+    match endpoint {
+        Endpoint::CalendarEntries => get_calendar_events(storage, &conditions, &query).await,
+        Endpoint::CalendarIcs => get_calendar_ics(storage, &conditions).await,
+        Endpoint::TodoEntries => get_todo_entries(storage, &conditions).await,
+        Endpoint::ValidateCalendar => validate_calendar(storage, &conditions).await,
+        Endpoint::Settings => get_settings_response().await,
+        Endpoint::AddCalendarEntry => add_calendar_entry(storage, if_match, body).await,
+        Endpoint::UpdateCalendarEntry => update_calendar_entry(storage, id, if_match, body).await,
+        Endpoint::DeleteCalendarEntry => delete_calendar_entry(storage, id, if_match).await,
+        Endpoint::ImportIcs => import_ics(storage, if_match, body).await,
+        Endpoint::ChangeTodoEntries(change) => change_todo_entries(storage, change, if_match, body).await,
+    }
+}
+
+pub fn get_default_headers() -> HashMap<Header, String> {
+    hashmap! {
+        Header::ContentType => "application/json".to_string(),
+        Header::AccessControlAllowOrigin => "*".to_string(),
+        Header::AccessControlAllowHeaders => "Content-Type,X-Amz-Date,Authorization,X-Api-Key,X-Amz-Security-Token,If-Match,If-None-Match,If-Modified-Since".to_string(),
+        Header::AccessControlAllowMethods => "OPTIONS,POST,GET,PUT,DELETE".to_string(),
+        Header::AccessControlExposeHeaders => "ETag".to_string(),
+        Header::CacheControl => "no-store".to_string(),
+    }
+}
+
+/// 304 if the client has the data cached, otherwise 200 with the body made from the data. Either
+/// way the client may cache the response, but has to check that it's still current before using it.
+pub fn cached_data_response<T>(cached_data: BrowserCachedData<T>, mut headers: HashMap<Header, String>, body: impl FnOnce(T) -> Result<String, Error>) -> Result<Response, Error> {
+    headers.insert(Header::CacheControl, "private, no-cache".to_string());
+
+    Ok(match cached_data {
+        BrowserCachedData::InCache { etag, last_modified } => {
+            headers.insert(Header::ETag, etag);
+            headers.insert(Header::LastModified, format_http_date(last_modified));
+            Response { status_code: 304, headers, body: "".to_string()}
+        },
+        BrowserCachedData::NotInCache { data, etag, last_modified } => {
+            headers.insert(Header::ETag, etag);
+            headers.insert(Header::LastModified, format_http_date(last_modified));
+            Response { status_code: 200, headers, body: body(data)?}
+        }
+    })
+}
+
+/// Writes need the ETag the client last read the file with, so they can't overwrite changes made since.
+pub fn editable_data_or_precondition_response<T>(editable_data: EditableData<T>) -> Result<T, Response> {
+    match editable_data {
+        EditableData::NoVersion => Err(Response { status_code: 428, headers: get_default_headers(), body: "If-Match header is required".to_string()}),
+        EditableData::Changed => Err(precondition_failed_response()),
+        EditableData::Unchanged { data } => Ok(data)
+    }
+}
+
+pub fn precondition_failed_response() -> Response {
+    Response { status_code: 412, headers: get_default_headers(), body: "The file has changed since it was read".to_string()}
+}
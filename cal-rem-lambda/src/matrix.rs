@@ -1,4 +1,7 @@
+use async_trait::async_trait;
+use lambda_runtime::Error;
 use serde::{Deserialize, Serialize};
+use std::env::var;
 use reqwest::{
     Client,
     header::{
@@ -6,6 +9,7 @@ use reqwest::{
         HeaderMap
     }
 };
+use crate::notifier::Messenger;
 
 pub struct Matrix {
     pub server: String
@@ -75,14 +79,17 @@ impl Matrix {
             .await
     }
 
-    pub async fn authenticate_and_send_messages_to_room(&self, user: &str, password: &str, room_id: &str, messages: Vec<String>) {
+    /// Sends every message it can, and fails if any of them couldn't be sent.
+    pub async fn authenticate_and_send_messages_to_room(&self, user: &str, password: &str, room_id: &str, messages: Vec<String>) -> Result<(), Error> {
         let response = self.login(user, password).await;
+        let mut sent_all = false;
         
         match response {
             Ok(login_response) => {
                 match login_response {
                     MatrixResponse::Ok(login_response) => {
                         let token = login_response.access_token;
+                        sent_all = true;
                         for message in messages {
                             match self.send_msg_to_room(message.as_str(), &token, room_id).await {
                                 Ok(event_response) => {
@@ -90,11 +97,13 @@ impl Matrix {
                                         MatrixResponse::Ok(_) => {},
                                         MatrixResponse::Err(error_response) => {
                                             error_log("send message", &error_response);
+                                            sent_all = false;
                                         }
                                     }
                                 },
                                 Err(err) => {
                                     error_log("send message", &err);
+                                    sent_all = false;
                                 }
                             }
                         }
@@ -109,6 +118,23 @@ impl Matrix {
                 error_log("login", &err);
             }
         };
+
+        if sent_all { Ok(()) } else { Err("Not every message could be sent to Matrix".into()) }
+    }
+}
+
+/// The reminder room, with the server and login read from the environment when there is something to send.
+pub struct MatrixReminderRoom;
+
+#[async_trait]
+impl Messenger for MatrixReminderRoom {
+    async fn send_messages(&self, messages: Vec<String>) -> Result<(), Error> {
+        Matrix { server: var("MATRIX_SERVER")? }.authenticate_and_send_messages_to_room(
+            &var("MATRIX_USER")?,
+            &var("MATRIX_PW")?,
+            &var("MATRIX_REMINDER_ROOM")?,
+            messages
+        ).await
     }
 }
 
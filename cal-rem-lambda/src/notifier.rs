@@ -1,24 +1,52 @@
-use chrono::prelude::*;
+use async_trait::async_trait;
+use chrono::{prelude::*, Duration};
 use lambda_runtime::Error;
 use rand::{Rng, SeedableRng, rngs::SmallRng, seq::SliceRandom};
-use std::env::var;
-use crate::dynamodb::{get_value_from_cache, store_value_in_cache};
-use crate::matrix::Matrix;
+use cal_rem_shared::expand_recurring_entries;
+use crate::cache::Cache;
+use crate::matrix::MatrixReminderRoom;
 use crate::notify::{create_notifications_from_calendar, get_notifications_within_time_window};
 use crate::parser::parse_calendar_file;
-use crate::s3::get_object_as_string;
+use crate::settings::get_home_time_zone;
+use crate::storage::Storage;
 use crate::todo::parse_todo_file;
 
 
-pub async fn run_notifier() -> Result<(), Error> {
+const LAST_NOTIFICATION_TIME: &str = "last-notification-time";
+
+/// Where the notifications are sent.
+#[async_trait]
+pub trait Messenger: Send + Sync {
+    /// Sends every message it can, and fails if any of them couldn't be sent.
+    async fn send_messages(&self, messages: Vec<String>) -> Result<(), Error>;
+}
+
+/// The Matrix room MATRIX_REMINDER_ROOM on MATRIX_SERVER, logged in to as MATRIX_USER with MATRIX_PW.
+pub fn get_messenger() -> Box<dyn Messenger> {
+    Box::new(MatrixReminderRoom)
+}
+
+pub async fn run_notifier(storage: &dyn Storage, cache: &dyn Cache, messenger: &dyn Messenger) -> Result<(), Error> {
     let now = Utc::now().timestamp();
 
-    let previous_now = get_value_from_cache("last-notification-time".to_string()).await?
+    let last_notification_time = cache.get(LAST_NOTIFICATION_TIME).await?;
+    let previous_now = last_notification_time.as_ref()
         .map_or(now - 3600, |s| {
             s.parse::<i64>().unwrap_or(now - 3600)
         });
 
-    let notifications = create_notifications_from_calendar(&parse_calendar_file(&get_object_as_string(var("S3_MAIN_BUCKET")?, "calendar.txt".to_string()).await?));
+    let home_time_zone = get_home_time_zone()?;
+
+    let today = Utc::now().with_timezone(&home_time_zone).naive_local().date();
+    let entries = parse_calendar_file(&storage.get("calendar.txt").await?.data).entries;
+
+    // occurrences are needed as far ahead as the earliest reminder, which is 24 hours unless an entry asks for more.
+    let longest_notice = entries.iter()
+        .flat_map(|entry| entry.reminders.iter().flatten())
+        .map(|reminder| Duration::minutes(reminder.minutes_before as i64))
+        .max()
+        .unwrap_or_else(Duration::zero);
+    let notifications = create_notifications_from_calendar(&expand_recurring_entries(&entries, today - Duration::days(1), today + Duration::days(2) + longest_notice), home_time_zone);
     let notifications_within_time_window = get_notifications_within_time_window(&notifications, Utc::now().timestamp(), previous_now);
     
     let mut messages: Vec<String> = notifications_within_time_window.iter().map(|notification| {
@@ -27,9 +55,10 @@ pub async fn run_notifier() -> Result<(), Error> {
 
     {
         let mut rng = SmallRng::from_entropy();
-        let now = Utc::now();
+        let now = Utc::now().with_timezone(&home_time_zone);
         if now.hour() > 8 && now.hour() < 23 && rng.gen::<f64>() < 1.0/60.0 {
-            let mut todo_entries = parse_todo_file(&get_object_as_string(var("S3_MAIN_BUCKET")?, "todo.txt".to_string()).await?);
+            let mut todo_entries: Vec<String> = parse_todo_file(&storage.get("todo.txt").await?.data)
+                .into_iter().filter(|todo| !todo.done).map(|todo| todo.description).collect();
             todo_entries.shuffle(&mut rng);
             todo_entries.first().map(|entry| messages.push(entry.clone()));
         }
@@ -37,15 +66,75 @@ pub async fn run_notifier() -> Result<(), Error> {
     
     if messages.len() > 0 {
         println!("{:?}", messages);
-        Matrix { server: var("MATRIX_SERVER")? }.authenticate_and_send_messages_to_room(
-            &var("MATRIX_USER")?,
-            &var("MATRIX_PW")?,
-            &var("MATRIX_REMINDER_ROOM")?,
-            messages
-        ).await;
+        // the time window moves on even if the send fails, as the messages that did get through
+        // would otherwise be sent again on every run until they all do.
+        if let Err(error) = messenger.send_messages(messages).await {
+            log::error!("Notifications up to {} weren't all sent: {}", now, error);
+        }
+    }
+
+    if !cache.compare_and_swap(LAST_NOTIFICATION_TIME, last_notification_time.as_deref(), now.to_string(), None).await? {
+        log::warn!("Another run moved the notification window on first, so some notifications may have been sent twice");
     }
 
-    store_value_in_cache("last-notification-time".to_string(), now.to_string()).await?;
-    
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cal_rem_shared::{Entry, format_calendar_file, num_to_month};
+    use crate::cache::MemoryCache;
+    use crate::storage::MemoryStorage;
+    use std::sync::Mutex;
+
+    // Keeps the messages it's given, and fails to send them if `fails`.
+    #[derive(Default)]
+    struct TestMessenger {
+        fails: bool,
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Messenger for TestMessenger {
+        async fn send_messages(&self, messages: Vec<String>) -> Result<(), Error> {
+            self.sent.lock().unwrap().extend(messages);
+            if self.fails { Err("The messages couldn't be sent".into()) } else { Ok(()) }
+        }
+    }
+
+    // A calendar with an entry yesterday, whose notifications are due within the last two days.
+    fn calendar_with_entry_yesterday() -> String {
+        let yesterday = Utc::now().with_timezone(&get_home_time_zone().unwrap()).naive_local().date() - Duration::days(1);
+        format_calendar_file(&[Entry::on(yesterday.year() as u32, num_to_month(yesterday.month()).unwrap(), Some(yesterday.day()), "Tannlege")])
+    }
+
+    #[tokio::test]
+    async fn run_notifier_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", "2020\n\nJanuar\n1. Nyttår\n").with_object("todo.txt", "");
+        let cache = MemoryCache::default();
+        cache.put(LAST_NOTIFICATION_TIME, "1600000000".to_string(), None).await.unwrap();
+
+        run_notifier(&storage, &cache, &TestMessenger::default()).await.unwrap();
+        let last_notification_time = cache.get(LAST_NOTIFICATION_TIME).await.unwrap().unwrap().parse::<i64>().unwrap();
+        assert!(last_notification_time > 1600000000 && last_notification_time <= Utc::now().timestamp());
+    }
+
+    #[tokio::test]
+    async fn failed_send_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", &calendar_with_entry_yesterday()).with_object("todo.txt", "");
+        let cache = MemoryCache::default();
+        let last_notification_time = (Utc::now() - Duration::days(2)).timestamp().to_string();
+        cache.put(LAST_NOTIFICATION_TIME, last_notification_time.clone(), None).await.unwrap();
+
+        let messenger = TestMessenger { fails: true, ..TestMessenger::default() };
+        run_notifier(&storage, &cache, &messenger).await.unwrap();
+        assert!(messenger.sent.lock().unwrap().iter().any(|message| message.contains("Tannlege")));
+        assert_ne!(Some(last_notification_time), cache.get(LAST_NOTIFICATION_TIME).await.unwrap());
+
+        // the next run doesn't send the same notifications again.
+        let messenger = TestMessenger::default();
+        run_notifier(&storage, &cache, &messenger).await.unwrap();
+        assert!(!messenger.sent.lock().unwrap().iter().any(|message| message.contains("Tannlege")));
+    }
 }
\ No newline at end of file
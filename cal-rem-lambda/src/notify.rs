@@ -10,16 +10,45 @@ pub struct Notification {
     pub msg: String
 }
 
-pub fn create_notifications_from_calendar(entries: &Vec<Entry>) -> Vec<Notification> {
+pub fn create_notifications_from_calendar(entries: &Vec<Entry>, home_time_zone: Tz) -> Vec<Notification> {
     let mut notifications: Vec<Notification> = entries.iter().map(|entry| {
-        let event_time = entry.get_oslo_date_time();
+        // entries in other zones are moved to the home zone, so the 23:00 reminder is 23:00 at home.
+        let event_time = match entry.get_date_time(home_time_zone) {
+            Ok(event_time) => event_time.with_timezone(&home_time_zone),
+            Err(err) => {
+                // one bad entry shouldn't stop the reminders for all the others.
+                log::warn!("Skipping \"{}\": {}", entry.description, err);
+                return vec![];
+            }
+        };
         
         let msg = entry.create_message();
+
+        // tentative entries ask for the date to be confirmed rather than announcing the event.
+        let (short_notice, medium_notice, notice_24h) = if entry.uncertain_date {
+            ("Bekreft dato, om 20 min?", "Bekreft dato", "Bekreft dato, i morgen?")
+        } else {
+            ("Om 20 min", "husk", "I morgen")
+        };
         
         let mut v = Vec::new();
-        v.push(Notification { time: utc_notification_time_short_notice(event_time), msg: format!("Om 20 min: {}", msg.clone()) });
-        v.push(Notification { time: utc_notification_time_medium_notice(event_time), msg: format!("husk: {}", msg.clone()) });
-        v.push(Notification { time: utc_notification_time_24h_notice(event_time), msg: format!("I morgen: {}", msg.clone()) });
+        match &entry.reminders {
+            None => {
+                v.push(Notification { time: utc_notification_time_short_notice(event_time), msg: format!("{}: {}", short_notice, msg.clone()) });
+                v.push(Notification { time: utc_notification_time_medium_notice(event_time), msg: format!("{}: {}", medium_notice, msg.clone()) });
+                v.push(Notification { time: utc_notification_time_24h_notice(event_time), msg: format!("{}: {}", notice_24h, msg.clone()) });
+            },
+            Some(reminders) => {
+                for reminder in reminders {
+                    let notice = if entry.uncertain_date {
+                        format!("Bekreft dato, om {}?", describe_minutes(reminder.minutes_before))
+                    } else {
+                        format!("Om {}", describe_minutes(reminder.minutes_before))
+                    };
+                    v.push(Notification { time: utc_notification_time_before(event_time, reminder.minutes_before), msg: format!("{}: {}", notice, msg.clone()) });
+                }
+            }
+        }
                 
         v
     }).flatten().collect();
@@ -59,4 +88,83 @@ fn utc_notification_time_medium_notice(event_time: DateTime<Tz>) -> DateTime<Utc
 
 fn utc_notification_time_24h_notice(event_time: DateTime<Tz>) -> DateTime<Utc> {
     event_time.with_timezone(&Utc).checked_sub_signed(Duration::hours(24)).unwrap()
-}
\ No newline at end of file
+}
+
+fn utc_notification_time_before(event_time: DateTime<Tz>, minutes_before: u32) -> DateTime<Utc> {
+    event_time.with_timezone(&Utc).checked_sub_signed(Duration::minutes(minutes_before as i64)).unwrap()
+}
+
+// uses the largest unit that divides the offset evenly, so 10080 minutes is "1 uke".
+fn describe_minutes(minutes: u32) -> String {
+    let units = [(60 * 24 * 7, "uke", "uker"), (60 * 24, "dag", "dager"), (60, "time", "timer")];
+
+    for (minutes_in_unit, singular, plural) in units.iter() {
+        if minutes > 0 && minutes % minutes_in_unit == 0 {
+            let amount = minutes / minutes_in_unit;
+            return format!("{} {}", amount, if amount == 1 { singular } else { plural });
+        }
+    }
+
+    format!("{} min", minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cal_rem_shared::{Month, HourMinute, Reminder};
+    use chrono_tz::Europe::{London, Oslo};
+
+    fn entry(reminders: Option<Vec<Reminder>>) -> Entry {
+        Entry {
+            start_time: Some(HourMinute { hour: 10, minute: 0 }),
+            reminders,
+            ..Entry::on(2021, Month::June, Some(15), "Passport appointment")
+        }
+    }
+
+    #[test]
+    fn reminder_override_test() {
+        let notifications = create_notifications_from_calendar(&vec![entry(None)], Oslo);
+        assert_eq!(3, notifications.len());
+
+        let notifications = create_notifications_from_calendar(&vec![entry(Some(vec![Reminder { minutes_before: 60 * 24 * 7 }, Reminder { minutes_before: 60 }]))], Oslo);
+        let times: Vec<String> = notifications.iter().map(|notification| notification.time.to_rfc3339()).collect();
+        assert_eq!(vec!["2021-06-08T08:00:00+00:00", "2021-06-15T07:00:00+00:00"], times);
+        assert_eq!("Om 1 uke: June 15., 10.00: Passport appointment", notifications[0].msg);
+        assert_eq!("Om 1 time: June 15., 10.00: Passport appointment", notifications[1].msg);
+
+        assert!(create_notifications_from_calendar(&vec![entry(Some(vec![]))], Oslo).is_empty());
+    }
+
+    #[test]
+    fn invalid_entry_test() {
+        let mut invalid = entry(None);
+        invalid.start_date = Some(31);
+
+        let notifications = create_notifications_from_calendar(&vec![invalid, entry(None)], Oslo);
+        assert_eq!(3, notifications.len());
+    }
+
+    #[test]
+    fn time_zone_test() {
+        // 10.00 in Oslo is 08.00 UTC in June
+        let notifications = create_notifications_from_calendar(&vec![entry(Some(vec![Reminder { minutes_before: 0 }]))], Oslo);
+        assert_eq!("2021-06-15T08:00:00+00:00", notifications[0].time.to_rfc3339());
+
+        let notifications = create_notifications_from_calendar(&vec![entry(Some(vec![Reminder { minutes_before: 0 }]))], London);
+        assert_eq!("2021-06-15T09:00:00+00:00", notifications[0].time.to_rfc3339());
+
+        // An entry's own zone wins over the home zone, and is shown in the message
+        let mut flight = entry(Some(vec![Reminder { minutes_before: 0 }]));
+        flight.time_zone = Some("Europe/London".to_string());
+        let notifications = create_notifications_from_calendar(&vec![flight.clone()], Oslo);
+        assert_eq!("2021-06-15T09:00:00+00:00", notifications[0].time.to_rfc3339());
+        assert_eq!("Om 0 min: June 15., 10.00 Europe/London: Passport appointment", notifications[0].msg);
+
+        // The evening-before reminder is at 23.00 in the home zone, wherever the entry is
+        flight.reminders = None;
+        flight.start_time = Some(HourMinute { hour: 7, minute: 0 });
+        let notifications = create_notifications_from_calendar(&vec![flight], Oslo);
+        assert_eq!("2021-06-14T21:00:00+00:00", notifications[1].time.to_rfc3339());
+    }
+}
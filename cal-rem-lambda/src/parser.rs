@@ -1,8 +1,48 @@
+use chrono::NaiveDate;
+use chrono_tz::Tz;
 use lazy_static::lazy_static;
 use regex::Regex;
-use cal_rem_shared::{Entry, Month, HourMinute};
+use serde::Serialize;
+use std::collections::HashSet;
+use cal_rem_shared::{Entry, Month, HourMinute, Reminder, Frequency, Recurrence, RecurrenceLimit, YearMonthDate, month_to_num, num_to_month};
 
-fn year_regex(unparsed_entry: &str) -> Option<u32> {
+#[derive(Debug, Serialize, Eq, PartialEq, Clone, Copy)]
+pub enum DiagnosticKind {
+    UnrecognizedEntry,
+    MissingHeader,
+    DateOutOfRange,
+    TimeOutOfRange,
+    UnknownTimeZone,
+    InvalidDateRange,
+    UnknownAnnotation,
+    DuplicateId,
+    RecurrenceWithoutDate,
+    TrailingText,
+}
+
+#[derive(Debug, Serialize, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+pub struct ParsedCalendar {
+    pub entries: Vec<Entry>,
+    // the line each entry was read from, counted from 1 like diagnostics.
+    pub lines: Vec<usize>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    // columns are counted in characters from 1, while regex offsets are in bytes.
+    fn new(line: usize, unparsed_line: &str, byte_offset: usize, kind: DiagnosticKind, message: String) -> Diagnostic {
+        Diagnostic { line, column: unparsed_line[..byte_offset].chars().count() + 1, kind, message }
+    }
+}
+
+pub fn year_regex(unparsed_entry: &str) -> Option<u32> {
     lazy_static! {
         static ref YEAR: Regex = Regex::new(r"(?x)
         ^
@@ -20,7 +60,7 @@ fn year_regex(unparsed_entry: &str) -> Option<u32> {
     Some(valid_year["year"].parse().unwrap())
 }
 
-fn month_regex(unparsed_entry: &str) -> Option<Month> {
+pub fn month_regex(unparsed_entry: &str) -> Option<Month> {
     lazy_static! {
         static ref MONTH: Regex = Regex::new(r"(?x)
         ^
@@ -52,24 +92,24 @@ fn month_regex(unparsed_entry: &str) -> Option<Month> {
     }
 }
 
-fn event_entry_regex(unparsed_entry: &str, year: u32, month: Month) -> Option<Entry> {
+fn event_entry_regex(unparsed_entry: &str, year: u32, month: Month, line: usize, diagnostics: &mut Vec<Diagnostic>) -> Option<Entry> {
     lazy_static! {
         static ref DATE: Regex = Regex::new(r"(?x)
         ^
         (
             (?P<date>\d+) | 
             (?P<maybe_date>\d+)\? | 
-            (?P<start_date>\d+)-(?P<end_date>\d+) | 
-            (?P<maybe_start_date>\d+)-(?P<maybe_end_date>\d+)\? | 
+            (?P<maybe_start_date>\d+)-(?P<maybe_end_date>\d+)(\.(?P<maybe_end_month>\d{1,2})(\.(?P<maybe_end_year>\d{4}))?)?\? | # before the certain range, which would otherwise match part of it
+            (?P<start_date>\d+)-(?P<end_date>\d+)(\.(?P<end_month>\d{1,2})(\.(?P<end_year>\d{4}))?)? | 
             \?
         )
         \.
 
-        (?P<description>[^@\[]*) # stop when we reach @ or [
+        (?P<description>[^@\[{]*) # stop when we reach @, [ or {
 
         (
             @
-            (?P<location>[^\[]*) # stop when we reach [
+            (?P<location>[^\[{]*) # stop when we reach [ or {
         )?
 
         (
@@ -80,6 +120,9 @@ fn event_entry_regex(unparsed_entry: &str, year: u32, month: Month) -> Option<En
             )
             \]
         )?
+
+        \s*
+        (?P<annotations>(\{[^}]*\}\s*)*) # zero or more {...} blocks
 ").unwrap();
     }
 
@@ -88,31 +131,76 @@ fn event_entry_regex(unparsed_entry: &str, year: u32, month: Month) -> Option<En
         None => return None
     };
 
-    let mut entry = Entry {
-        year: year,
-        month: month,
-        start_date: None,
-        end_date: None,
-        start_time: None,
-        end_time: None,
-        description: "".to_string(),
-        location: None
+    let mut entry = Entry::on(year, month, None, "");
+
+    let start_date = valid_entry.name("date")
+        .or_else(|| valid_entry.name("maybe_date"))
+        .or_else(|| valid_entry.name("start_date"))
+        .or_else(|| valid_entry.name("maybe_start_date"));
+    let end_date = valid_entry.name("end_date").or_else(|| valid_entry.name("maybe_end_date"));
+    let end_month = valid_entry.name("end_month").or_else(|| valid_entry.name("maybe_end_month"));
+    let end_year = valid_entry.name("end_year").or_else(|| valid_entry.name("maybe_end_year"));
+
+    let (end_month, end_year) = match end_month {
+        Some(end_month) => match end_month.as_str().parse().ok().and_then(num_to_month) {
+            // a range into an earlier month without a year, like 28-3.1., ends the following year.
+            Some(valid_month) => (valid_month, end_year.map_or(if valid_month < month { year + 1 } else { year }, |y| y.as_str().parse().unwrap())),
+            None => {
+                diagnostics.push(Diagnostic::new(line, unparsed_entry, end_month.start(), DiagnosticKind::DateOutOfRange,
+                    format!("{} is not a month", end_month.as_str())));
+                return None;
+            }
+        },
+        None => (month, year)
     };
 
-    if valid_entry.name("date") != None {
-        entry.start_date = Some(valid_entry["date"].parse().unwrap());
-    } else if valid_entry.name("maybe_date") != None {
-        entry.start_date = Some(valid_entry["maybe_date"].parse().unwrap());
-    } else if valid_entry.name("start_date") != None {
-        entry.start_date = Some(valid_entry["start_date"].parse().unwrap());
-        entry.end_date = Some(valid_entry["end_date"].parse().unwrap());
-    } else if valid_entry.name("maybe_start_date") != None {
-        entry.start_date = Some(valid_entry["maybe_start_date"].parse().unwrap());
-        entry.end_date = Some(valid_entry["maybe_end_date"].parse().unwrap());
+    let mut dates = vec![];
+    for (date, year, month) in start_date.iter().map(|date| (date, year, month)).chain(end_date.iter().map(|date| (date, end_year, end_month))) {
+        match date.as_str().parse().ok().and_then(|d| NaiveDate::from_ymd_opt(year as i32, month_to_num(month), d)) {
+            Some(valid_date) => dates.push(valid_date),
+            None => {
+                diagnostics.push(Diagnostic::new(line, unparsed_entry, date.start(), DiagnosticKind::DateOutOfRange,
+                    format!("{:?} {} has no date {}", month, year, date.as_str())));
+                return None;
+            }
+        }
+    }
+
+    if let (Some(end), [start, end_date]) = (end_date, dates.as_slice()) {
+        if end_date < start {
+            diagnostics.push(Diagnostic::new(line, unparsed_entry, end.start(), DiagnosticKind::InvalidDateRange,
+                format!("The range ends on {} before it starts on {}", end_date, start)));
+            return None;
+        }
+    }
+
+    for (hour, minute) in &[("hour", "minute"), ("start_hour", "start_minute"), ("end_hour", "end_minute")] {
+        if let Some(hour_match) = valid_entry.name(hour) {
+            let hour: u32 = hour_match.as_str().parse().unwrap();
+            let minute: u32 = valid_entry[*minute].parse().unwrap();
+
+            if hour > 23 || minute > 59 {
+                diagnostics.push(Diagnostic::new(line, unparsed_entry, hour_match.start(), DiagnosticKind::TimeOutOfRange,
+                    format!("{:02}.{:02} is not a valid time of day", hour, minute)));
+                return None;
+            }
+        }
     }
 
-    entry.description = valid_entry["description"].trim().to_string();
-    valid_entry.name("location").map(|loc| { entry.location = Some(loc.as_str().trim().to_string()) });
+    entry.start_date = start_date.map(|date| date.as_str().parse().unwrap());
+    entry.end_date = end_date.map(|date| date.as_str().parse().unwrap());
+    entry.end_month = end_date.map(|_| end_month);
+    entry.end_year = end_date.map(|_| end_year);
+    entry.uncertain_date = valid_entry.name("maybe_date").is_some() || valid_entry.name("maybe_start_date").is_some();
+
+    let (description, mut tags) = extract_tags(&valid_entry["description"]);
+    entry.description = description;
+    valid_entry.name("location").map(|loc| {
+        let (location, location_tags) = extract_tags(loc.as_str());
+        tags.extend(location_tags);
+        entry.location = Some(location).filter(|location| !location.is_empty());
+    });
+    entry.tags = tags;
 
     // usage of map with unused return.
     valid_entry.name("hour").map(|hour| {
@@ -134,31 +222,246 @@ fn event_entry_regex(unparsed_entry: &str, year: u32, month: Month) -> Option<En
         });
     });
 
+    let annotations_offset = valid_entry.name("annotations").unwrap().start();
+
+    for (offset, annotation) in annotations(&valid_entry["annotations"]) {
+        let offset = annotations_offset + offset;
+
+        if let Some(recurrence) = recurrence_regex(annotation) {
+            if entry.start_date.is_none() {
+                diagnostics.push(Diagnostic::new(line, unparsed_entry, offset, DiagnosticKind::RecurrenceWithoutDate,
+                    "An entry without a date cannot recur".to_string()));
+            }
+            match recurrence {
+                Ok(recurrence) => entry.recurrence = Some(recurrence),
+                Err(message) => diagnostics.push(Diagnostic::new(line, unparsed_entry, offset, DiagnosticKind::DateOutOfRange, message))
+            }
+        } else if let Some(reminders) = reminders_regex(annotation) {
+            entry.reminders = Some(reminders);
+        } else if let Some(id) = id_regex(annotation) {
+            entry.id = id.to_string();
+        } else if let Some(time_zone) = time_zone_regex(annotation) {
+            if time_zone.parse::<Tz>().is_ok() {
+                entry.time_zone = Some(time_zone.to_string());
+            } else {
+                diagnostics.push(Diagnostic::new(line, unparsed_entry, offset, DiagnosticKind::UnknownTimeZone,
+                    format!("Unknown time zone {}, expected a name like Europe/London", time_zone)));
+            }
+        } else {
+            diagnostics.push(Diagnostic::new(line, unparsed_entry, offset, DiagnosticKind::UnknownAnnotation,
+                format!("Unknown annotation {{{}}}", annotation)));
+        }
+    }
+
+    let end_of_entry = valid_entry.get(0).unwrap().end();
+    if end_of_entry < unparsed_entry.trim_end().len() {
+        diagnostics.push(Diagnostic::new(line, unparsed_entry, end_of_entry, DiagnosticKind::TrailingText,
+            format!("Ignoring \"{}\" after the entry", unparsed_entry[end_of_entry..].trim_end())));
+    }
+
     return Some(entry)
 }
 
-pub fn parse_calendar_file(file: &String) -> Vec<Entry> {
+// Removes #tags from a description or location and returns them lowercased.
+fn extract_tags(text: &str) -> (String, Vec<String>) {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"(^|\s)#(?P<tag>[^\W\d_]\w*)").unwrap();
+    }
+
+    let tags: Vec<String> = TAG.captures_iter(text).map(|tag| tag["tag"].to_lowercase()).collect();
+
+    if tags.is_empty() {
+        (text.trim().to_string(), tags)
+    } else {
+        (TAG.replace_all(text, "").split_whitespace().collect::<Vec<&str>>().join(" "), tags)
+    }
+}
+
+// Returns each {...} block's contents along with the byte offset of its opening brace.
+fn annotations(unparsed_annotations: &str) -> Vec<(usize, &str)> {
+    lazy_static! {
+        static ref ANNOTATION: Regex = Regex::new(r"\{(?P<annotation>[^}]*)\}").unwrap();
+    }
+
+    ANNOTATION.captures_iter(unparsed_annotations)
+        .map(|annotation| (annotation.get(0).unwrap().start(), annotation.name("annotation").unwrap().as_str().trim()))
+        .collect()
+}
+
+// Err if the recurrence is until a date that doesn't exist, like 31.2.2022.
+fn recurrence_regex(annotation: &str) -> Option<Result<Recurrence, String>> {
+    lazy_static! {
+        static ref RECURRENCE: Regex = Regex::new(r"(?x)
+        ^
+        (
+            (?P<weekly>ukentlig|hver\s+uke) |
+            hver\s+(?P<interval>\d+)\.\s*uke |
+            (?P<monthly>månedlig|hver\s+måned) |
+            (?P<yearly>årlig|hvert\s+år)
+        )
+        (
+            \s+til\s+(?P<until_date>\d{1,2})\.(?P<until_month>\d{1,2})\.(?P<until_year>\d{4}) |
+            \s+(?P<count>\d+)\s+ganger
+        )?
+        $
+").unwrap();
+    }
+
+    let valid_recurrence = match RECURRENCE.captures(annotation) {
+        Some(recurrence) => recurrence,
+        None => return None
+    };
+
+    let frequency = if valid_recurrence.name("weekly").is_some() {
+        Frequency::Weekly { interval: 1 }
+    } else if let Some(interval) = valid_recurrence.name("interval") {
+        match interval.as_str().parse().ok()? {
+            0 => return None,
+            interval => Frequency::Weekly { interval }
+        }
+    } else if valid_recurrence.name("monthly").is_some() {
+        Frequency::Monthly
+    } else {
+        Frequency::Yearly
+    };
+
+    let limit = if let Some(until_date) = valid_recurrence.name("until_date") {
+        let year: u32 = valid_recurrence["until_year"].parse().unwrap();
+        let month: u32 = valid_recurrence["until_month"].parse().unwrap();
+        let date: u32 = until_date.as_str().parse().unwrap();
+        match num_to_month(month).filter(|_| NaiveDate::from_ymd_opt(year as i32, month, date).is_some()) {
+            Some(month) => Some(RecurrenceLimit::Until(YearMonthDate { year, month, date })),
+            None => return Some(Err(format!("There is no date {}.{}.{} to recur until", date, month, year)))
+        }
+    } else {
+        match valid_recurrence.name("count") {
+            Some(count) => Some(RecurrenceLimit::Count(count.as_str().parse().ok()?)),
+            None => None
+        }
+    };
+
+    Some(Ok(Recurrence { frequency, limit }))
+}
+
+fn id_regex(annotation: &str) -> Option<&str> {
+    lazy_static! {
+        static ref ID: Regex = Regex::new(r"^id\s+(?P<id>[A-Za-z0-9_-]+)$").unwrap();
+    }
+
+    ID.captures(annotation).map(|id| id.name("id").unwrap().as_str())
+}
+
+fn time_zone_regex(annotation: &str) -> Option<&str> {
+    lazy_static! {
+        static ref TIME_ZONE: Regex = Regex::new(r"^tidssone\s+(?P<time_zone>\S+)$").unwrap();
+    }
+
+    TIME_ZONE.captures(annotation).map(|time_zone| time_zone.name("time_zone").unwrap().as_str())
+}
+
+fn reminders_regex(annotation: &str) -> Option<Vec<Reminder>> {
+    lazy_static! {
+        static ref REMINDERS: Regex = Regex::new(r"(?x)
+        ^
+        (
+            (?P<none>ingen\s+varsel) |
+            varsel\s+(?P<offsets>\d+\s*[mtdu](\s*,?\s*\d+\s*[mtdu])*)
+        )
+        $
+").unwrap();
+        static ref OFFSET: Regex = Regex::new(r"(?P<amount>\d+)\s*(?P<unit>[mtdu])").unwrap();
+    }
+
+    let valid_reminders = match REMINDERS.captures(annotation) {
+        Some(reminders) => reminders,
+        None => return None
+    };
+
+    if valid_reminders.name("none").is_some() {
+        return Some(vec![]);
+    }
+
+    OFFSET.captures_iter(&valid_reminders["offsets"]).map(|offset| {
+        let amount: u32 = offset["amount"].parse().ok()?;
+        let minutes_in_unit = match &offset["unit"] {
+            "m" => 1,
+            "t" => 60,
+            "d" => 60 * 24,
+            _ => 60 * 24 * 7
+        };
+
+        amount.checked_mul(minutes_in_unit).map(|minutes_before| Reminder { minutes_before })
+    }).collect()
+}
+
+// Lines that start like an entry but aren't parsed as one are most likely typos.
+fn looks_like_entry(line: &str) -> bool {
+    lazy_static! {
+        static ref ENTRY_START: Regex = Regex::new(r"^\s*(\d+|\?)").unwrap();
+    }
+
+    year_regex(line).is_none() && ENTRY_START.is_match(line)
+}
+
+pub fn parse_calendar_file(file: &String) -> ParsedCalendar {
     let mut year: Option<u32> = None;
     let mut month: Option<Month> = None;
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    let mut ids: HashSet<String> = HashSet::new();
 
-    file.split("\n").filter_map(|line| {
+    let (lines, entries) = file.split("\n").enumerate().filter_map(|(index, line)| {
+        let line_number = index + 1;
         year_regex(line).map(|y| year = Some(y));
         month_regex(line).map(|m| month = Some(m));
         if year.is_some() && month.is_some() {
-            event_entry_regex(line, year.clone().unwrap(), month.clone().unwrap())
+            let diagnostic_count = diagnostics.len();
+            let entry = event_entry_regex(line, year.clone().unwrap(), month.clone().unwrap(), line_number, &mut diagnostics);
+
+            if entry.is_none() && diagnostics.len() == diagnostic_count && looks_like_entry(line) {
+                diagnostics.push(Diagnostic::new(line_number, line, 0, DiagnosticKind::UnrecognizedEntry,
+                    "Expected an entry like \"N. description @ location [hh.mm-hh.mm]\"".to_string()));
+            }
+
+            entry.map(|mut entry| {
+                if entry.id.is_empty() {
+                    entry.id = entry.content_id();
+                } else if ids.contains(&entry.id) {
+                    diagnostics.push(Diagnostic::new(line_number, line, line.find("{id").unwrap_or(0), DiagnosticKind::DuplicateId,
+                        format!("The id {} is already used by another entry", entry.id)));
+                }
+
+                // identical entries on the same date get -2, -3 and so on in file order.
+                let id = entry.id.clone();
+                let mut n = 1;
+                while ids.contains(&entry.id) {
+                    n += 1;
+                    entry.id = format!("{}-{}", id, n);
+                }
+
+                ids.insert(entry.id.clone());
+                (line_number, entry)
+            })
         } else {
+            if looks_like_entry(line) {
+                diagnostics.push(Diagnostic::new(line_number, line, 0, DiagnosticKind::MissingHeader,
+                    "Entry before the first year and month header".to_string()));
+            }
+
             None
         }
-    }).collect()
+    }).unzip();
+
+    ParsedCalendar { entries, lines, diagnostics }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cal_rem_shared::{format_calendar_file, format_entry};
 
     #[test]
     fn event_parsing_test() {
-        let event = event_entry_regex("10. Event with start time, end time, and location @ Place A [18.30-20.00]", 2020, Month::May).unwrap();
+        let event = event_entry_regex("10. Event with start time, end time, and location @ Place A [18.30-20.00]", 2020, Month::May, 1, &mut vec![]).unwrap();
         assert_eq!(10, event.start_date.unwrap());
         assert!(event.end_date.is_none());
         assert_eq!("Event with start time, end time, and location", event.description);
@@ -166,7 +469,7 @@ mod tests {
         assert_eq!(HourMinute { hour: 18, minute: 30 }, event.start_time.unwrap());
         assert_eq!(HourMinute { hour: 20, minute: 00 }, event.end_time.unwrap());
         
-        let event = event_entry_regex("11. Event with start time and location @ Place B [19.30]", 2020, Month::May).unwrap();
+        let event = event_entry_regex("11. Event with start time and location @ Place B [19.30]", 2020, Month::May, 1, &mut vec![]).unwrap();
         assert_eq!(11, event.start_date.unwrap());
         assert!(event.end_date.is_none());
         assert_eq!("Event with start time and location", event.description);
@@ -174,7 +477,7 @@ mod tests {
         assert_eq!(HourMinute { hour: 19, minute: 30 }, event.start_time.unwrap());
         assert!(event.end_time.is_none());
         
-        let event = event_entry_regex("17. Event with start time and no location [10.00]", 2020, Month::May).unwrap();
+        let event = event_entry_regex("17. Event with start time and no location [10.00]", 2020, Month::May, 1, &mut vec![]).unwrap();
         assert_eq!(17, event.start_date.unwrap());
         assert!(event.end_date.is_none());
         assert_eq!("Event with start time and no location", event.description);
@@ -182,23 +485,46 @@ mod tests {
         assert_eq!(HourMinute { hour: 10, minute: 0 }, event.start_time.unwrap());
         assert!(event.end_time.is_none());
         
-        let event = event_entry_regex("24?. Event with uncertain date", 2020, Month::May).unwrap();
+        let event = event_entry_regex("24?. Event with uncertain date", 2020, Month::May, 1, &mut vec![]).unwrap();
         assert_eq!(24, event.start_date.unwrap());
         assert!(event.end_date.is_none());
+        assert!(event.uncertain_date);
         assert_eq!("Event with uncertain date", event.description);
         assert!(event.location.is_none());
         assert!(event.start_time.is_none());
         assert!(event.end_time.is_none());
         
-        let event = event_entry_regex("5-11. Multiple day event with start time and location @ Place C [11.00]", 2020, Month::July).unwrap();
+        let event = event_entry_regex("5-11. Multiple day event with start time and location @ Place C [11.00]", 2020, Month::July, 1, &mut vec![]).unwrap();
         assert_eq!(5, event.start_date.unwrap());
         assert_eq!(11, event.end_date.unwrap());
+        assert_eq!(Month::July, event.end_month.unwrap());
+        assert_eq!(2020, event.end_year.unwrap());
+        assert!(!event.uncertain_date);
         assert_eq!("Multiple day event with start time and location", event.description);
         assert_eq!("Place C", event.location.unwrap());
         assert_eq!(HourMinute { hour: 11, minute: 0 }, event.start_time.unwrap());
         assert!(event.end_time.is_none());
         
-        let event = event_entry_regex("?. Event with unknown date (while stile belonging to a month)", 2020, Month::October).unwrap();
+        let event = event_entry_regex("28-3.1. Multiple day event into the next year", 2020, Month::December, 1, &mut vec![]).unwrap();
+        assert_eq!(28, event.start_date.unwrap());
+        assert_eq!(3, event.end_date.unwrap());
+        assert_eq!(Month::January, event.end_month.unwrap());
+        assert_eq!(2021, event.end_year.unwrap());
+        assert_eq!("Multiple day event into the next year", event.description);
+
+        let event = event_entry_regex("30-2.2.2022?. Multiple month event with uncertain dates", 2021, Month::November, 1, &mut vec![]).unwrap();
+        assert_eq!(30, event.start_date.unwrap());
+        assert_eq!(2, event.end_date.unwrap());
+        assert_eq!(Month::February, event.end_month.unwrap());
+        assert_eq!(2022, event.end_year.unwrap());
+        assert!(event.uncertain_date);
+
+        let event = event_entry_regex("5-11?. Multiple day event with uncertain dates", 2020, Month::July, 1, &mut vec![]).unwrap();
+        assert_eq!(5, event.start_date.unwrap());
+        assert_eq!(11, event.end_date.unwrap());
+        assert!(event.uncertain_date);
+
+        let event = event_entry_regex("?. Event with unknown date (while stile belonging to a month)", 2020, Month::October, 1, &mut vec![]).unwrap();
         assert!(event.start_date.is_none());
         assert!(event.end_date.is_none());
         assert_eq!("Event with unknown date (while stile belonging to a month)", event.description);
@@ -206,15 +532,167 @@ mod tests {
         assert!(event.start_time.is_none());
         assert!(event.end_time.is_none());
         
-        let event = event_entry_regex("Some text that is not interpreted as an event.", 2020, Month::May);
+        let event = event_entry_regex("Some text that is not interpreted as an event.", 2020, Month::May, 1, &mut vec![]);
         assert!(event.is_none());
         
         // A year marker is not an event
-        let event = event_entry_regex("2021", 2020, Month::May);
+        let event = event_entry_regex("2021", 2020, Month::May, 1, &mut vec![]);
         assert!(event.is_none());
         
         // A month marker is not an event
-        let event = event_entry_regex("Mai", 2020, Month::April);
+        let event = event_entry_regex("Mai", 2020, Month::April, 1, &mut vec![]);
         assert!(event.is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tag_parsing_test() {
+        let event = event_entry_regex("10. Meeting #jobb with the #Board @ Office #oslo [10.00]", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Meeting with the", event.description);
+        assert_eq!("Office", event.location.unwrap());
+        assert_eq!(vec!["jobb", "board", "oslo"], event.tags);
+
+        // Numbers after # are not tags
+        let event = event_entry_regex("10. Meeting in room #3 #familie", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Meeting in room #3", event.description);
+        assert_eq!(vec!["familie"], event.tags);
+
+        let event = event_entry_regex("10. Dinner @ #familie", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Dinner", event.description);
+        assert!(event.location.is_none());
+        assert_eq!(vec!["familie"], event.tags);
+    }
+
+    #[test]
+    fn recurrence_parsing_test() {
+        let event = event_entry_regex("4. Training @ Gym [18.00] {ukentlig}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Training", event.description);
+        assert_eq!("Gym", event.location.unwrap());
+        assert_eq!(HourMinute { hour: 18, minute: 0 }, event.start_time.unwrap());
+        assert_eq!(Recurrence { frequency: Frequency::Weekly { interval: 1 }, limit: None }, event.recurrence.unwrap());
+
+        let event = event_entry_regex("4. Cleaning {hver 2. uke til 30.6.2021}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Cleaning", event.description);
+        assert_eq!(Recurrence {
+            frequency: Frequency::Weekly { interval: 2 },
+            limit: Some(RecurrenceLimit::Until(YearMonthDate { year: 2021, month: Month::June, date: 30 }))
+        }, event.recurrence.unwrap());
+
+        let event = event_entry_regex("20. Pay rent @ Bank {månedlig 12 ganger}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Bank", event.location.unwrap());
+        assert_eq!(Recurrence { frequency: Frequency::Monthly, limit: Some(RecurrenceLimit::Count(12)) }, event.recurrence.unwrap());
+
+        let event = event_entry_regex("17. Birthday {årlig}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!(Recurrence { frequency: Frequency::Yearly, limit: None }, event.recurrence.unwrap());
+
+        let event = event_entry_regex("17. Passport appointment @ Police station [10.00] {varsel 1u, 1t}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Police station", event.location.unwrap());
+        assert_eq!(vec![Reminder { minutes_before: 60 * 24 * 7 }, Reminder { minutes_before: 60 }], event.reminders.unwrap());
+
+        let event = event_entry_regex("17. National day {årlig} {ingen varsel}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!(Recurrence { frequency: Frequency::Yearly, limit: None }, event.recurrence.unwrap());
+        assert!(event.reminders.unwrap().is_empty());
+
+        let event = event_entry_regex("17. Dentist {varsel 2d 30m}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!(vec![Reminder { minutes_before: 60 * 24 * 2 }, Reminder { minutes_before: 30 }], event.reminders.unwrap());
+
+        let event = event_entry_regex("17. Birthday", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert!(event.reminders.is_none());
+        assert!(event.time_zone.is_none());
+
+        let event = event_entry_regex("17. Flight home @ Heathrow [18.45] {tidssone Europe/London}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Europe/London", event.time_zone.unwrap());
+
+        // Unknown annotations are ignored
+        let event = event_entry_regex("17. Birthday {sometimes}", 2021, Month::May, 1, &mut vec![]).unwrap();
+        assert_eq!("Birthday", event.description);
+        assert!(event.recurrence.is_none());
+    }
+
+    #[test]
+    fn calendar_diagnostics_test() {
+        let calendar = parse_calendar_file(&[
+            "3. Before any header",
+            "2021",
+            "Juni",
+            "1. Valid entry [18.00]",
+            "31. No such date",
+            "2. Late night [25.00]",
+            "4 Missing dot",
+            "5. Birthday {sometimes}",
+            "?. Unknown date {ukentlig}",
+            "6. Entry [18.00] with trailing text",
+            "Some notes that are not entries",
+            "10-5. Backwards range",
+            "10-5.13. Unknown end month",
+            "11. Flight [10.00] {tidssone Europe/Lundun}",
+            "12. First {id same}",
+            "13. Second {id same}",
+            "14. Training {ukentlig til 31.2.2022}",
+        ].join("\n"));
+
+        assert_eq!(vec!["Valid entry", "Birthday", "Unknown date", "Entry", "Flight", "First", "Second", "Training"],
+            calendar.entries.iter().map(|entry| entry.description.as_str()).collect::<Vec<&str>>());
+
+        let diagnostics: Vec<(usize, usize, DiagnosticKind)> = calendar.diagnostics.iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.column, diagnostic.kind))
+            .collect();
+        assert_eq!(vec![
+            (1, 1, DiagnosticKind::MissingHeader),
+            (5, 1, DiagnosticKind::DateOutOfRange),
+            (6, 16, DiagnosticKind::TimeOutOfRange),
+            (7, 1, DiagnosticKind::UnrecognizedEntry),
+            (8, 13, DiagnosticKind::UnknownAnnotation),
+            (9, 17, DiagnosticKind::RecurrenceWithoutDate),
+            (10, 18, DiagnosticKind::TrailingText),
+            (12, 4, DiagnosticKind::InvalidDateRange),
+            (13, 6, DiagnosticKind::DateOutOfRange),
+            (14, 20, DiagnosticKind::UnknownTimeZone),
+            (16, 12, DiagnosticKind::DuplicateId),
+            (17, 14, DiagnosticKind::DateOutOfRange),
+        ], diagnostics);
+    }
+
+    #[test]
+    fn id_test() {
+        let file = "2021\nMai\n1. Event [10.00]\n2. Party\n2. Party\n3. Passport {id pass}\n".to_string();
+        let ids: Vec<String> = parse_calendar_file(&file).entries.iter().map(|entry| entry.id.clone()).collect();
+        assert_eq!(format!("{}-2", ids[1]), ids[2]);
+        assert_eq!("pass", ids[3]);
+
+        // Editing other lines, or the entry's time, keeps the id
+        let edited = "2021\nMai\n1. New event\n1. Event @ Office [12.00]\n2. Party\n2. Party\n3. Passport appointment {id pass}\n".to_string();
+        let edited_ids: Vec<String> = parse_calendar_file(&edited).entries.iter().map(|entry| entry.id.clone()).collect();
+        assert_eq!(ids, edited_ids[1..].to_vec());
+    }
+
+    #[test]
+    fn format_round_trip_test() {
+        let file = [
+            "2020",
+            "Desember",
+            "?. Unknown date #familie",
+            "5. Event #jobb @ Place A [08.30-20.00] {varsel 1u, 90m}",
+            "24?. Uncertain date",
+            "28-3.1. Into the next year {ingen varsel}",
+            "",
+            "2021",
+            "Mai",
+            "4. Training @ Gym [18.00] {hver 2. uke til 30.6.2021}",
+            "5-7.5.2022?. Over a year {årlig 3 ganger}",
+            "17. National day {årlig}",
+            "18. Flight home @ Heathrow [18.45] {tidssone Europe/London}",
+            "18. Flight home @ Heathrow [18.45] {tidssone Europe/London} {id return-flight}",
+            "19. Passport appointment {varsel 1u} {id pass}",
+            "",
+        ].join("\n");
+
+        let entries = parse_calendar_file(&file).entries;
+        assert_eq!(file, format_calendar_file(&entries));
+        assert_eq!(entries, parse_calendar_file(&format_calendar_file(&entries)).entries);
+
+        // Entries written in other ways still parse back to the same entries
+        let entries = parse_calendar_file(&"2021\nMai\n10.Meeting #jobb with @ Office #oslo [9.00] {hver uke}  {varsel 60m}\n".to_string()).entries;
+        assert_eq!("10. Meeting with #jobb #oslo @ Office [09.00] {ukentlig} {varsel 1t}", format_entry(&entries[0]));
+        assert_eq!(entries, parse_calendar_file(&format_calendar_file(&entries)).entries);
+    }
+}
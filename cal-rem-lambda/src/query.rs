@@ -0,0 +1,153 @@
+use chrono::{prelude::*, Duration};
+use std::collections::HashMap;
+use cal_rem_shared::{Entry, Month, expand_recurring_entries, month_to_num};
+
+/// The query parameters of /get-all-calendar-entries:
+/// `from` and `to` (YYYY-MM-DD, inclusive) limit the entries to the ones overlapping those dates,
+/// `include_past=true` keeps entries that ended before yesterday, `q` searches the description
+/// and location, `tag` keeps entries with any of the comma separated tags, and `limit` returns
+/// at most that many entries.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct CalendarQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub include_past: bool,
+    pub search: Option<String>,
+    pub tags: Vec<String>,
+    pub limit: Option<usize>,
+}
+
+impl CalendarQuery {
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<CalendarQuery, String> {
+        let date = |name: &str| parameters.get(name)
+            .map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| format!("{} should be a date like 2021-06-15", name)))
+            .transpose();
+
+        let query = CalendarQuery {
+            from: date("from")?,
+            to: date("to")?,
+            include_past: match parameters.get("include_past").map(|include_past| include_past.as_str()) {
+                None | Some("false") => false,
+                Some("true") => true,
+                _ => return Err("include_past should be true or false".to_string())
+            },
+            search: parameters.get("q").map(|search| search.trim().to_lowercase()).filter(|search| !search.is_empty()),
+            tags: parameters.get("tag").map_or(vec![], |tag| tag.split(',').map(|tag| tag.trim().trim_start_matches('#').to_lowercase()).collect()),
+            limit: parameters.get("limit").map(|limit| limit.parse().map_err(|_| "limit should be a number".to_string())).transpose()?,
+        };
+
+        match (query.from, query.to) {
+            (Some(from), Some(to)) if from > to => Err("from should be before to".to_string()),
+            _ => Ok(query)
+        }
+    }
+
+    /// Identifies the query for ETags, together with the day it's asked on, since which entries
+    /// are in the past changes at midnight.
+    pub fn cache_key(&self, today: NaiveDate) -> String {
+        format!("{:?}-{}", self, today)
+    }
+
+    /// Expands recurring entries within the query's dates, a year ahead if it doesn't have a `to`,
+    /// and keeps the matching entries in date order.
+    pub fn apply(&self, entries: &[Entry], today: NaiveDate) -> Vec<Entry> {
+        // past entries are kept until the day after they end, so late evening events don't disappear at midnight.
+        let from = self.from.or(if self.include_past { None } else { Some(today - Duration::days(1)) });
+        let first_date = entries.iter().filter_map(|entry| entry.start_naive_date()).min().unwrap_or(today);
+        let expanded = expand_recurring_entries(entries, from.unwrap_or(first_date), self.to.unwrap_or(today + Duration::days(365)));
+
+        let matches = expanded.into_iter()
+            .filter(|entry| match span(entry) {
+                Some((start, end)) => from.map_or(true, |from| end >= from) && self.to.map_or(true, |to| start <= to),
+                None => true
+            })
+            .filter(|entry| self.tags.is_empty() || entry.tags.iter().any(|tag| self.tags.contains(tag)))
+            .filter(|entry| self.search.as_ref().map_or(true, |search| {
+                entry.description.to_lowercase().contains(search) || entry.location.as_ref().map_or(false, |location| location.to_lowercase().contains(search))
+            }));
+
+        match self.limit {
+            Some(limit) => matches.take(limit).collect(),
+            None => matches.collect()
+        }
+    }
+}
+
+// The first and last day of an entry, or of its month if it has no date. None for dates that don't exist.
+fn span(entry: &Entry) -> Option<(NaiveDate, NaiveDate)> {
+    let first_of_month = NaiveDate::from_ymd_opt(entry.year as i32, month_to_num(entry.month), 1)?;
+
+    match entry.start_date {
+        Some(_) => {
+            let start = entry.start_naive_date()?;
+            let end = if entry.end_date.is_some() { entry.end_naive_date()? } else { start };
+            Some((start, end))
+        },
+        None => {
+            let (year, month) = if entry.month == Month::December { (entry.year as i32 + 1, 1) } else { (entry.year as i32, month_to_num(entry.month) + 1) };
+            Some((first_of_month, NaiveDate::from_ymd_opt(year, month, 1)?.pred_opt()?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cal_rem_shared::{Frequency, Recurrence};
+
+    fn query(parameters: &[(&str, &str)]) -> Result<CalendarQuery, String> {
+        CalendarQuery::from_parameters(&parameters.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+
+    fn descriptions(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.description.as_str()).collect()
+    }
+
+    #[test]
+    fn parameters_test() {
+        assert_eq!(Ok(CalendarQuery::default()), query(&[]));
+        assert_eq!(Ok(CalendarQuery {
+            from: NaiveDate::from_ymd_opt(2021, 6, 1),
+            include_past: true,
+            search: Some("dentist".to_string()),
+            tags: vec!["jobb".to_string(), "ferie".to_string()],
+            limit: Some(5),
+            ..CalendarQuery::default()
+        }), query(&[("from", "2021-06-01"), ("include_past", "true"), ("q", " Dentist "), ("tag", "jobb,#ferie"), ("limit", "5")]));
+
+        assert!(query(&[("from", "1.6.2021")]).is_err());
+        assert!(query(&[("limit", "-1")]).is_err());
+        assert!(query(&[("include_past", "yes")]).is_err());
+        assert!(query(&[("from", "2021-06-02"), ("to", "2021-06-01")]).is_err());
+    }
+
+    #[test]
+    fn apply_test() {
+        let today = NaiveDate::from_ymd_opt(2021, 6, 15).unwrap();
+        let mut dentist = Entry::on(2021, Month::June, Some(20), "Dentist");
+        dentist.location = Some("Town".to_string());
+        let mut weekly = Entry::on(2021, Month::May, Some(4), "Training");
+        weekly.recurrence = Some(Recurrence { frequency: Frequency::Weekly { interval: 1 }, limit: None });
+        let entries = vec![
+            Entry::on(2021, Month::June, Some(1), "Past"),
+            Entry::on(2021, Month::June, Some(14), "Yesterday"),
+            Entry::on(2021, Month::June, None, "Some time in June"),
+            dentist,
+            weekly,
+            Entry::on(2023, Month::January, Some(1), "Far ahead"),
+        ];
+
+        let upcoming = query(&[]).unwrap().apply(&entries, today);
+        assert_eq!(vec!["Some time in June", "Yesterday", "Training", "Dentist", "Training"], descriptions(&upcoming[..5]));
+        assert_eq!(Some(&"Far ahead"), descriptions(&upcoming).last());
+
+        let with_past = query(&[("include_past", "true"), ("to", "2021-06-10")]).unwrap().apply(&entries, today);
+        assert_eq!(vec!["Training", "Training", "Training", "Training", "Some time in June", "Past", "Training", "Training"], descriptions(&with_past));
+
+        let range = query(&[("from", "2021-06-16"), ("to", "2021-06-30"), ("limit", "2")]).unwrap().apply(&entries, today);
+        assert_eq!(vec!["Some time in June", "Dentist"], descriptions(&range));
+
+        let search = query(&[("q", "town")]).unwrap().apply(&entries, today);
+        assert_eq!(vec!["Dentist"], descriptions(&search));
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+/// Matches a method and path against routes like `PUT /calendar-entries/{id}`, where `{id}`
+/// is a path parameter. HEAD requests match GET routes.
+pub struct Router<R> {
+    routes: Vec<(&'static str, Vec<Segment>, R)>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum RouteMatch<R> {
+    Found(R, HashMap<String, String>),
+    // the path exists, but not with this method.
+    MethodNotAllowed(Vec<&'static str>),
+    NotFound,
+}
+
+enum Segment {
+    Literal(&'static str),
+    Parameter(&'static str),
+}
+
+impl<R: Clone> Default for Router<R> {
+    fn default() -> Router<R> {
+        Router::new()
+    }
+}
+
+impl<R: Clone> Router<R> {
+    pub fn new() -> Router<R> {
+        Router { routes: vec![] }
+    }
+
+    pub fn route(mut self, method: &'static str, pattern: &'static str, route: R) -> Router<R> {
+        let segments = segments(pattern).map(|segment| {
+            match segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')) {
+                Some(name) => Segment::Parameter(name),
+                None => Segment::Literal(segment)
+            }
+        }).collect();
+
+        self.routes.push((method, segments, route));
+        self
+    }
+
+    pub fn find(&self, method: &str, path: &str) -> RouteMatch<R> {
+        let method = if method.eq_ignore_ascii_case("HEAD") { "GET" } else { method };
+
+        let found = self.routes.iter()
+            .filter(|(route_method, _, _)| route_method.eq_ignore_ascii_case(method))
+            .find_map(|(_, pattern, route)| parameters(pattern, path).map(|parameters| (route.clone(), parameters)));
+
+        match found {
+            Some((route, parameters)) => RouteMatch::Found(route, parameters),
+            None => {
+                let allowed_methods = self.allowed_methods(path);
+                if allowed_methods.is_empty() { RouteMatch::NotFound } else { RouteMatch::MethodNotAllowed(allowed_methods) }
+            }
+        }
+    }
+
+    /// The methods the path can be requested with, for the Allow and CORS headers. Empty if no route has the path.
+    pub fn allowed_methods(&self, path: &str) -> Vec<&'static str> {
+        let mut methods: Vec<&'static str> = vec![];
+
+        for (method, pattern, _) in &self.routes {
+            if parameters(pattern, path).is_some() && !methods.contains(method) {
+                methods.push(method);
+                if *method == "GET" {
+                    methods.push("HEAD");
+                }
+            }
+        }
+
+        if !methods.is_empty() {
+            methods.push("OPTIONS");
+        }
+
+        methods
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn parameters(pattern: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let path: Vec<&str> = segments(path).collect();
+    if path.len() != pattern.len() {
+        return None;
+    }
+
+    let mut parameters = HashMap::new();
+    for (segment, part) in pattern.iter().zip(path) {
+        match segment {
+            Segment::Literal(literal) if *literal == part => {},
+            Segment::Parameter(name) => { parameters.insert(name.to_string(), part.to_string()); },
+            _ => return None
+        }
+    }
+
+    Some(parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router<&'static str> {
+        Router::new()
+            .route("GET", "/calendar-entries", "list")
+            .route("POST", "/calendar-entries", "add")
+            .route("PUT", "/calendar-entries/{id}", "update")
+            .route("DELETE", "/calendar-entries/{id}", "delete")
+            .route("POST", "/calendar-entries/import", "import")
+    }
+
+    fn found(route: &'static str, parameters: &[(&str, &str)]) -> RouteMatch<&'static str> {
+        RouteMatch::Found(route, parameters.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+
+    #[test]
+    fn routing_test() {
+        let router = router();
+        assert_eq!(found("list", &[]), router.find("GET", "/calendar-entries"));
+        assert_eq!(found("list", &[]), router.find("HEAD", "/calendar-entries/"));
+        assert_eq!(found("update", &[("id", "abc-2")]), router.find("PUT", "/calendar-entries/abc-2"));
+        assert_eq!(found("import", &[]), router.find("POST", "/calendar-entries/import"));
+        assert_eq!(RouteMatch::MethodNotAllowed(vec!["PUT", "DELETE", "OPTIONS"]), router.find("GET", "/calendar-entries/abc"));
+        assert_eq!(RouteMatch::NotFound, router.find("GET", "/calendar-entries/abc/def"));
+        assert_eq!(RouteMatch::NotFound, router.find("GET", "/unknown"));
+    }
+
+    #[test]
+    fn allowed_methods_test() {
+        let router = router();
+        assert_eq!(vec!["GET", "HEAD", "POST", "OPTIONS"], router.allowed_methods("/calendar-entries"));
+        assert_eq!(vec!["PUT", "DELETE", "POST", "OPTIONS"], router.allowed_methods("/calendar-entries/import"));
+        assert!(router.allowed_methods("/unknown").is_empty());
+    }
+}
@@ -1,35 +1,120 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
 use futures::stream::TryStreamExt;
 use lambda_runtime::Error;
-use s3::{ByteStream, Client};
+use s3::{ByteStream, Client, model::BucketVersioningStatus, output::GetObjectOutput};
+use crate::etag::{if_match_matches, if_none_match_matches};
+use crate::storage::{ObjectIfChanged, ObjectVersion, Storage, StoredObject};
 
-pub enum BrowserCachedData<T> {
-    InCache,
-    NotInCache { data: T, etag: String }
+/// A bucket with versioning turned on, so earlier versions can be listed.
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
 }
 
-pub async fn get_object_as_string(bucket: String, key: String) -> Result<String, Error> {
-    let client = Client::from_env();
-    let res = client.get_object().bucket(bucket).key(key).send().await?;
-    let body = res.body.map_ok(|b| b.to_vec()).try_concat().await?;
-    Ok(String::from_utf8(body)?)
+impl S3Storage {
+    pub fn new(bucket: String) -> S3Storage {
+        S3Storage { client: Client::from_env(), bucket }
+    }
+}
+
+// S3 always gives the last modified time, but the SDK leaves it optional.
+fn epoch_time(seconds: Option<i64>) -> DateTime<Utc> {
+    Utc.timestamp(seconds.unwrap_or(0), 0)
 }
 
-pub async fn get_object_as_string_if_etags_differ(bucket: String, key: String, etag: Option<String>) -> Result<BrowserCachedData<String>, Error> {
-    let client = Client::from_env();
-    let res = client.get_object().bucket(bucket).key(key).send().await?;
-    let source_etag = res.e_tag.unwrap();
+async fn stored_object(res: GetObjectOutput) -> Result<StoredObject, Error> {
+    let etag = res.e_tag.clone().ok_or("S3 returned an object without an ETag")?;
+    let last_modified = epoch_time(res.last_modified.as_ref().map(|last_modified| last_modified.epoch_seconds()));
+    let body = res.body.map_ok(|b| b.to_vec()).try_concat().await?;
+    Ok(StoredObject { data: String::from_utf8(body)?, etag, last_modified })
+}
 
-    if etag.is_none() || source_etag != etag.unwrap() {
-        let body = res.body.map_ok(|b| b.to_vec()).try_concat().await?;
-        Ok(BrowserCachedData::NotInCache { data: String::from_utf8(body)?, etag: source_etag })
-    } else {
-        Ok(BrowserCachedData::InCache)
+// Of the versions, newest first and starting with the put's, the one the put overwrote without
+// having checked it: the one just before the put's, if that isn't the version that matched.
+fn overwritten_version<'a>(versions: &'a [ObjectVersion], matched_etag: &str) -> Option<&'a ObjectVersion> {
+    match versions {
+        [_, previous, ..] if previous.etag != matched_etag => Some(previous),
+        _ => None
     }
 }
 
-pub async fn save_string_as_object(s: String, bucket: String, key: String) -> Result<(), Error> {
-    let client = Client::from_env();
-    let buffer = ByteStream::from(Vec::from(s.as_bytes()));
-    let _f = client.put_object().bucket(bucket).key(key).body(buffer).send().await?;
-    Ok(())
-}
\ No newline at end of file
+#[async_trait]
+impl Storage for S3Storage {
+    async fn get(&self, key: &str) -> Result<StoredObject, Error> {
+        let res = self.client.get_object().bucket(&self.bucket).key(key).send().await?;
+        stored_object(res).await
+    }
+
+    // a HEAD first, so the body is only downloaded when it has changed. If-None-Match on the GET
+    // would save the HEAD, but this SDK doesn't reliably tell S3's 304 apart from other errors.
+    async fn get_if_etag_differs(&self, key: &str, if_none_match: &str) -> Result<ObjectIfChanged, Error> {
+        let res = self.client.head_object().bucket(&self.bucket).key(key).send().await?;
+        let etag = res.e_tag.ok_or("S3 returned an object without an ETag")?;
+        if if_none_match_matches(if_none_match, &etag) {
+            let last_modified = epoch_time(res.last_modified.map(|last_modified| last_modified.epoch_seconds()));
+            return Ok(ObjectIfChanged::Unchanged { etag, last_modified });
+        }
+
+        Ok(ObjectIfChanged::Changed { object: self.get(key).await? })
+    }
+
+    // S3 can't make the put itself conditional, so the versions are checked after it: if the version
+    // before the new one isn't the one that matched, a hand edit or another write came in between
+    // the check and the put, and it is put back on top. If a write has already landed on top of
+    // the put, that one is newer and is left as it is.
+    async fn put_if_match(&self, key: &str, data: String, if_match: &str) -> Result<Option<String>, Error> {
+        // without versioning the put would overwrite the other write for good, so nothing is written.
+        let versioning = self.client.get_bucket_versioning().bucket(&self.bucket).send().await?;
+        if versioning.status != Some(BucketVersioningStatus::Enabled) {
+            return Err(format!("The bucket {} doesn't have versioning turned on", self.bucket).into());
+        }
+
+        let matched_etag = if if_match == "*" {
+            None
+        } else {
+            let res = self.client.head_object().bucket(&self.bucket).key(key).send().await?;
+            match res.e_tag {
+                Some(etag) if if_match_matches(if_match, &etag) => Some(etag),
+                _ => return Ok(None)
+            }
+        };
+
+        let buffer = ByteStream::from(Vec::from(data.as_bytes()));
+        let res = self.client.put_object().bucket(&self.bucket).key(key).body(buffer).send().await?;
+        let version_id = res.version_id.ok_or("S3 returned no version id, so the bucket doesn't have versioning turned on")?;
+        let etag = res.e_tag.ok_or("S3 returned no ETag for the put")?;
+
+        if let Some(matched_etag) = matched_etag {
+            let versions = self.list_versions(key).await?;
+            if versions.first().map_or(true, |latest| latest.version_id != version_id) {
+                return Ok(None);
+            }
+            if let Some(overwritten) = overwritten_version(&versions, &matched_etag) {
+                self.client.copy_object().bucket(&self.bucket).key(key)
+                    .copy_source(format!("{}/{}?versionId={}", self.bucket, key, overwritten.version_id))
+                    .send().await?;
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(etag))
+    }
+
+    async fn list_versions(&self, key: &str) -> Result<Vec<ObjectVersion>, Error> {
+        let res = self.client.list_object_versions().bucket(&self.bucket).prefix(key).send().await?;
+
+        let mut versions: Vec<ObjectVersion> = res.versions.unwrap_or_default().into_iter()
+            .filter(|version| version.key.as_deref() == Some(key))
+            .map(|version| ObjectVersion {
+                version_id: version.version_id.unwrap_or_else(|| "null".to_string()),
+                etag: version.e_tag.unwrap_or_default(),
+                last_modified: epoch_time(version.last_modified.map(|last_modified| last_modified.epoch_seconds())),
+                is_latest: version.is_latest
+            })
+            .collect();
+
+        versions.sort_by(|a, b| b.last_modified.cmp(&a.last_modified));
+        Ok(versions)
+    }
+}
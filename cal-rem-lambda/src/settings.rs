@@ -0,0 +1,23 @@
+use chrono_tz::Tz;
+use lambda_runtime::Error;
+use std::env::var;
+use cal_rem_shared::Settings;
+use crate::{get_default_headers, Header, Response};
+
+/// Reads the calendar's settings from the environment. CALENDAR_TIME_ZONE defaults to Europe/Oslo.
+pub fn get_settings() -> Settings {
+    Settings { time_zone: var("CALENDAR_TIME_ZONE").unwrap_or_else(|_| "Europe/Oslo".to_string()) }
+}
+
+pub fn get_home_time_zone() -> Result<Tz, Error> {
+    Ok(get_settings().time_zone.parse::<Tz>()?)
+}
+
+pub async fn get_settings_response() -> Result<Response, Error> {
+    get_home_time_zone()?;
+
+    // the settings only change when the lambda is deployed again.
+    let mut headers = get_default_headers();
+    headers.insert(Header::CacheControl, "private, max-age=3600".to_string());
+    Ok(Response { status_code: 200, headers, body: serde_json::to_string(&get_settings())? })
+}
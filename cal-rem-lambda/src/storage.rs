@@ -0,0 +1,354 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
+use lambda_runtime::Error;
+use std::collections::HashMap;
+use std::env::var;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use cal_rem_shared::fnv1a;
+use crate::etag::{if_match_matches, if_match_matches_object_or_variant, if_none_match_matches, tags_with_suffix};
+use crate::s3::S3Storage;
+
+pub struct StoredObject {
+    pub data: String,
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+pub struct ObjectVersion {
+    pub version_id: String,
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    pub is_latest: bool,
+}
+
+/// The validators of a conditional GET, for data the client already has.
+#[derive(Default)]
+pub struct ConditionalRequest {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<DateTime<Utc>>,
+}
+
+impl ConditionalRequest {
+    // If-Modified-Since is only used when there's no If-None-Match, and Last-Modified only has whole seconds.
+    fn is_unchanged(&self, etag: &str, last_modified: DateTime<Utc>) -> bool {
+        match (&self.if_none_match, self.if_modified_since) {
+            (Some(if_none_match), _) => if_none_match_matches(if_none_match, etag),
+            (None, Some(if_modified_since)) => last_modified.timestamp() <= if_modified_since.timestamp(),
+            (None, None) => false
+        }
+    }
+}
+
+/// An object read with If-None-Match, which is only sent when it has changed.
+pub enum ObjectIfChanged {
+    Unchanged { etag: String, last_modified: DateTime<Utc> },
+    Changed { object: StoredObject }
+}
+
+// A 304 sends the same ETag and Last-Modified as the 200 it stands in for.
+pub enum BrowserCachedData<T> {
+    InCache { etag: String, last_modified: DateTime<Utc> },
+    NotInCache { data: T, etag: String, last_modified: DateTime<Utc> }
+}
+
+// An object that's about to be changed, checked against the ETag the client last read it with.
+pub enum EditableData<T> {
+    NoVersion,
+    Changed,
+    Unchanged { data: T }
+}
+
+/// Where calendar.txt and todo.txt are kept, by key.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get(&self, key: &str) -> Result<StoredObject, Error>;
+
+    /// Unchanged if the object's ETag is one of those in the If-None-Match header.
+    async fn get_if_etag_differs(&self, key: &str, if_none_match: &str) -> Result<ObjectIfChanged, Error>;
+
+    /// Saves the object unless its ETag isn't one of those in the If-Match header, `*` meaning
+    /// any, and returns its new ETag. None if it had changed.
+    async fn put_if_match(&self, key: &str, data: String, if_match: &str) -> Result<Option<String>, Error>;
+
+    /// The saved versions of the object, newest first.
+    async fn list_versions(&self, key: &str) -> Result<Vec<ObjectVersion>, Error>;
+}
+
+/// The S3_MAIN_BUCKET bucket.
+pub fn get_storage() -> Result<Box<dyn Storage>, Error> {
+    Ok(Box::new(S3Storage::new(var("S3_MAIN_BUCKET")?)))
+}
+
+pub async fn get_object_as_string_if_modified(storage: &dyn Storage, key: &str, conditions: &ConditionalRequest) -> Result<BrowserCachedData<String>, Error> {
+    let object = match &conditions.if_none_match {
+        Some(etag) => storage.get_if_etag_differs(key, etag).await?,
+        None => ObjectIfChanged::Changed { object: storage.get(key).await? }
+    };
+
+    Ok(match object {
+        ObjectIfChanged::Changed { object } if !conditions.is_unchanged(&object.etag, object.last_modified) => {
+            BrowserCachedData::NotInCache { data: object.data, etag: object.etag, last_modified: object.last_modified }
+        },
+        ObjectIfChanged::Changed { object } => BrowserCachedData::InCache { etag: object.etag, last_modified: object.last_modified },
+        ObjectIfChanged::Unchanged { etag, last_modified } => BrowserCachedData::InCache { etag, last_modified }
+    })
+}
+
+/// For responses computed from an object and something else, like query parameters, which
+/// `variant` identifies. Each variant gets its own ETag, which changes when the object does.
+/// `variant_modified` is when the response last changed for other reasons than the object,
+/// like midnight for responses that depend on the date.
+pub async fn get_object_variant_as_string_if_modified(storage: &dyn Storage, key: &str, conditions: &ConditionalRequest, variant: &str, variant_modified: DateTime<Utc>) -> Result<BrowserCachedData<String>, Error> {
+    // The variant ETags the client has tell which ETags of the object it has seen, so S3 only
+    // has to send the object when it has changed since.
+    let suffix = variant_suffix(variant);
+    let seen_etags = conditions.if_none_match.as_deref()
+        .map(|if_none_match| tags_with_suffix(if_none_match, &suffix))
+        .unwrap_or_default();
+
+    let object = if seen_etags.is_empty() {
+        storage.get(key).await?
+    } else {
+        match storage.get_if_etag_differs(key, &seen_etags.join(", ")).await? {
+            ObjectIfChanged::Changed { object } => object,
+            ObjectIfChanged::Unchanged { etag, last_modified } => {
+                return Ok(BrowserCachedData::InCache { etag: variant_etag(&etag, &suffix), last_modified: last_modified.max(variant_modified) });
+            }
+        }
+    };
+    let etag = variant_etag(&object.etag, &suffix);
+    let last_modified = object.last_modified.max(variant_modified);
+
+    if conditions.is_unchanged(&etag, last_modified) {
+        Ok(BrowserCachedData::InCache { etag, last_modified })
+    } else {
+        Ok(BrowserCachedData::NotInCache { data: object.data, etag, last_modified })
+    }
+}
+
+// if_match_matches_object_or_variant relies on the suffix being a - and 16 hex digits.
+fn variant_suffix(variant: &str) -> String {
+    format!("-{:016x}", fnv1a(variant.as_bytes()))
+}
+
+fn variant_etag(etag: &str, suffix: &str) -> String {
+    format!("\"{}{}\"", etag.trim_matches('"'), suffix)
+}
+
+/// The object with its current ETag, to save the changed object with. The ETag of a variant of
+/// the object will do as well as the object's own.
+pub async fn get_object_as_string_if_etag_matches(storage: &dyn Storage, key: &str, etag: Option<String>) -> Result<EditableData<StoredObject>, Error> {
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return Ok(EditableData::NoVersion)
+    };
+
+    let object = storage.get(key).await?;
+
+    if if_match_matches_object_or_variant(&etag, &object.etag) {
+        Ok(EditableData::Unchanged { data: object })
+    } else {
+        Ok(EditableData::Changed)
+    }
+}
+
+// Objects that aren't in S3 get an ETag from their contents, like S3 objects that aren't uploaded in parts.
+fn content_etag(data: &str) -> String {
+    format!("\"{:016x}\"", fnv1a(data.as_bytes()))
+}
+
+/// Objects as files in a directory. Only the latest version is kept.
+pub struct FileStorage {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl Storage for FileStorage {
+    async fn get(&self, key: &str) -> Result<StoredObject, Error> {
+        let data = fs::read_to_string(self.dir.join(key))?;
+        let last_modified = fs::metadata(self.dir.join(key))?.modified()?.into();
+        Ok(StoredObject { etag: content_etag(&data), data, last_modified })
+    }
+
+    async fn get_if_etag_differs(&self, key: &str, if_none_match: &str) -> Result<ObjectIfChanged, Error> {
+        let object = self.get(key).await?;
+        Ok(if if_none_match_matches(if_none_match, &object.etag) {
+            ObjectIfChanged::Unchanged { etag: object.etag, last_modified: object.last_modified }
+        } else {
+            ObjectIfChanged::Changed { object }
+        })
+    }
+
+    async fn put_if_match(&self, key: &str, data: String, if_match: &str) -> Result<Option<String>, Error> {
+        if if_match != "*" && !if_match_matches(if_match, &self.get(key).await?.etag) {
+            return Ok(None);
+        }
+
+        fs::write(self.dir.join(key), &data)?;
+        Ok(Some(content_etag(&data)))
+    }
+
+    async fn list_versions(&self, key: &str) -> Result<Vec<ObjectVersion>, Error> {
+        let object = self.get(key).await?;
+        Ok(vec![ObjectVersion { version_id: "null".to_string(), etag: object.etag, last_modified: object.last_modified, is_latest: true }])
+    }
+}
+
+// The data of each version, oldest first, with when it was saved.
+type Versions = Vec<(String, DateTime<Utc>)>;
+
+/// Objects with all their versions in memory, for tests.
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: Mutex<HashMap<String, Versions>>,
+}
+
+impl MemoryStorage {
+    pub fn with_object(self, key: &str, data: &str) -> MemoryStorage {
+        self.objects.lock().unwrap().entry(key.to_string()).or_default().push((data.to_string(), Utc::now()));
+        self
+    }
+
+    /// The latest version of the object, or None if there is none.
+    pub fn object(&self, key: &str) -> Option<String> {
+        self.latest_version(key).map(|(data, _)| data)
+    }
+
+    fn latest_version(&self, key: &str) -> Option<(String, DateTime<Utc>)> {
+        self.objects.lock().unwrap().get(key).and_then(|versions| versions.last()).cloned()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<StoredObject, Error> {
+        let (data, last_modified) = self.latest_version(key).ok_or_else(|| format!("No object with key {}", key))?;
+        Ok(StoredObject { etag: content_etag(&data), data, last_modified })
+    }
+
+    async fn get_if_etag_differs(&self, key: &str, if_none_match: &str) -> Result<ObjectIfChanged, Error> {
+        let object = self.get(key).await?;
+        Ok(if if_none_match_matches(if_none_match, &object.etag) {
+            ObjectIfChanged::Unchanged { etag: object.etag, last_modified: object.last_modified }
+        } else {
+            ObjectIfChanged::Changed { object }
+        })
+    }
+
+    async fn put_if_match(&self, key: &str, data: String, if_match: &str) -> Result<Option<String>, Error> {
+        let mut objects = self.objects.lock().unwrap();
+        let versions = objects.entry(key.to_string()).or_default();
+
+        if if_match != "*" && !versions.last().map_or(false, |(data, _)| if_match_matches(if_match, &content_etag(data))) {
+            return Ok(None);
+        }
+
+        let new_etag = content_etag(&data);
+        versions.push((data, Utc::now()));
+        Ok(Some(new_etag))
+    }
+
+    async fn list_versions(&self, key: &str) -> Result<Vec<ObjectVersion>, Error> {
+        let objects = self.objects.lock().unwrap();
+        let versions = objects.get(key).map_or(&[][..], |versions| versions.as_slice());
+
+        Ok(versions.iter().enumerate().rev().map(|(index, (data, last_modified))| ObjectVersion {
+            version_id: index.to_string(),
+            etag: content_etag(data),
+            last_modified: *last_modified,
+            is_latest: index + 1 == versions.len()
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn memory_storage_test() {
+        let storage = MemoryStorage::default().with_object("todo.txt", "Vaske\n");
+        let first = storage.get("todo.txt").await.unwrap();
+        assert_eq!("Vaske\n", first.data);
+        assert!(storage.get("calendar.txt").await.is_err());
+        assert!(matches!(storage.get_if_etag_differs("todo.txt", &first.etag).await.unwrap(), ObjectIfChanged::Unchanged { .. }));
+
+        let second_etag = storage.put_if_match("todo.txt", "Rydde\n".to_string(), &first.etag).await.unwrap().unwrap();
+        assert_ne!(first.etag, second_etag);
+        assert_eq!(None, storage.put_if_match("todo.txt", "Handle\n".to_string(), &first.etag).await.unwrap());
+        assert!(matches!(storage.get_if_etag_differs("todo.txt", &first.etag).await.unwrap(), ObjectIfChanged::Changed { object } if object.data == "Rydde\n"));
+
+        let versions = storage.list_versions("todo.txt").await.unwrap();
+        assert_eq!(vec![second_etag, first.etag], versions.iter().map(|version| version.etag.clone()).collect::<Vec<String>>());
+        assert!(versions[0].is_latest && !versions[1].is_latest);
+    }
+
+    #[tokio::test]
+    async fn browser_cache_test() {
+        let storage = MemoryStorage::default().with_object("calendar.txt", "2021\n");
+        let if_none_match = |etag: &str| ConditionalRequest { if_none_match: Some(etag.to_string()), ..ConditionalRequest::default() };
+        let if_modified_since = |time: DateTime<Utc>| ConditionalRequest { if_modified_since: Some(time), ..ConditionalRequest::default() };
+
+        let (etag, last_modified) = match get_object_as_string_if_modified(&storage, "calendar.txt", &ConditionalRequest::default()).await.unwrap() {
+            BrowserCachedData::NotInCache { etag, last_modified, .. } => (etag, last_modified),
+            BrowserCachedData::InCache { .. } => panic!("Nothing was cached")
+        };
+        match get_object_as_string_if_modified(&storage, "calendar.txt", &if_none_match(&etag)).await.unwrap() {
+            BrowserCachedData::InCache { etag: cached_etag, last_modified: cached_last_modified } => assert_eq!((&etag, last_modified), (&cached_etag, cached_last_modified)),
+            BrowserCachedData::NotInCache { .. } => panic!("The object hasn't changed")
+        }
+        assert!(matches!(get_object_as_string_if_modified(&storage, "calendar.txt", &if_modified_since(last_modified)).await.unwrap(), BrowserCachedData::InCache { .. }));
+        assert!(matches!(get_object_as_string_if_modified(&storage, "calendar.txt", &if_modified_since(last_modified - Duration::seconds(1))).await.unwrap(), BrowserCachedData::NotInCache { .. }));
+        // If-None-Match decides when both are sent.
+        let both = ConditionalRequest { if_none_match: Some("\"old\"".to_string()), if_modified_since: Some(last_modified) };
+        assert!(matches!(get_object_as_string_if_modified(&storage, "calendar.txt", &both).await.unwrap(), BrowserCachedData::NotInCache { .. }));
+
+        let midnight = last_modified + Duration::hours(1);
+        let variant_etag = match get_object_variant_as_string_if_modified(&storage, "calendar.txt", &if_none_match(&etag), "tag=jobb", midnight).await.unwrap() {
+            BrowserCachedData::NotInCache { etag, last_modified, .. } => {
+                assert_eq!(midnight, last_modified);
+                etag
+            },
+            BrowserCachedData::InCache { .. } => panic!("The variant has its own ETag")
+        };
+        match get_object_variant_as_string_if_modified(&storage, "calendar.txt", &if_none_match(&variant_etag), "tag=jobb", midnight).await.unwrap() {
+            BrowserCachedData::InCache { etag, last_modified } => assert_eq!((&variant_etag, midnight), (&etag, last_modified)),
+            BrowserCachedData::NotInCache { .. } => panic!("The variant hasn't changed")
+        }
+        assert!(matches!(get_object_variant_as_string_if_modified(&storage, "calendar.txt", &if_modified_since(last_modified), "tag=jobb", midnight).await.unwrap(), BrowserCachedData::NotInCache { .. }));
+        assert!(matches!(get_object_variant_as_string_if_modified(&storage, "calendar.txt", &if_none_match(&variant_etag), "tag=privat", midnight).await.unwrap(), BrowserCachedData::NotInCache { .. }));
+
+        assert!(matches!(get_object_as_string_if_etag_matches(&storage, "calendar.txt", None).await.unwrap(), EditableData::NoVersion));
+        assert!(matches!(get_object_as_string_if_etag_matches(&storage, "calendar.txt", Some("\"old\"".to_string())).await.unwrap(), EditableData::Changed));
+        assert!(matches!(get_object_as_string_if_etag_matches(&storage, "calendar.txt", Some(etag)).await.unwrap(), EditableData::Unchanged { .. }));
+        assert!(matches!(get_object_as_string_if_etag_matches(&storage, "calendar.txt", Some(variant_etag)).await.unwrap(), EditableData::Unchanged { .. }));
+    }
+
+    #[tokio::test]
+    async fn etag_forms_test() {
+        let storage = MemoryStorage::default().with_object("todo.txt", "Vaske\n");
+        let StoredObject { etag, last_modified, .. } = storage.get("todo.txt").await.unwrap();
+        let unquoted = etag.trim_matches('"').to_string();
+        let if_none_match = |etag: String| ConditionalRequest { if_none_match: Some(etag), ..ConditionalRequest::default() };
+
+        // Proxies that compress the response weaken the ETag, which still validates a GET.
+        assert!(matches!(get_object_as_string_if_modified(&storage, "todo.txt", &if_none_match(format!("W/{}", etag))).await.unwrap(), BrowserCachedData::InCache { .. }));
+        assert!(matches!(get_object_as_string_if_modified(&storage, "todo.txt", &if_none_match(unquoted.clone())).await.unwrap(), BrowserCachedData::InCache { .. }));
+        assert!(matches!(get_object_as_string_if_modified(&storage, "todo.txt", &if_none_match(format!("\"old\", {}", etag))).await.unwrap(), BrowserCachedData::InCache { .. }));
+
+        let variant_etag = match get_object_variant_as_string_if_modified(&storage, "todo.txt", &ConditionalRequest::default(), "done", last_modified).await.unwrap() {
+            BrowserCachedData::NotInCache { etag, .. } => etag,
+            BrowserCachedData::InCache { .. } => panic!("Nothing was cached")
+        };
+        assert!(matches!(get_object_variant_as_string_if_modified(&storage, "todo.txt", &if_none_match(format!("W/{}", variant_etag)), "done", last_modified).await.unwrap(), BrowserCachedData::InCache { .. }));
+
+        // but not a change.
+        assert!(matches!(get_object_as_string_if_etag_matches(&storage, "todo.txt", Some(format!("W/{}", etag))).await.unwrap(), EditableData::Changed));
+        assert!(matches!(get_object_as_string_if_etag_matches(&storage, "todo.txt", Some(unquoted.clone())).await.unwrap(), EditableData::Unchanged { .. }));
+        assert_eq!(None, storage.put_if_match("todo.txt", "Rydde\n".to_string(), &format!("W/{}", etag)).await.unwrap());
+        assert!(storage.put_if_match("todo.txt", "Rydde\n".to_string(), &unquoted).await.unwrap().is_some());
+    }
+}
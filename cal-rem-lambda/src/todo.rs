@@ -1,49 +1,270 @@
+use chrono::prelude::*;
 use lambda_runtime::Error;
-use std::env::var;
-use crate::{Header, Response, get_default_headers, s3::{BrowserCachedData, get_object_as_string_if_etags_differ}};
-use cal_rem_shared::Todo;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use crate::{Header, Response, cached_data_response, editable_data_or_precondition_response, get_default_headers, precondition_failed_response};
+use crate::storage::{ConditionalRequest, Storage, get_object_as_string_if_etag_matches, get_object_as_string_if_modified};
+use crate::settings::get_home_time_zone;
+use cal_rem_shared::{Todo, YearMonthDate, month_to_num, num_to_month};
 
-pub async fn get_todo_entries(etag: Option<String>) -> Result<Response, Error> {
-    let cached_data = get_object_as_string_if_etags_differ(var("S3_MAIN_BUCKET")?, "todo.txt".to_string(), etag).await?;
+const DONE_MARKER: &str = "--- Ferdig ---";
 
-    let mut headers = get_default_headers();
+#[derive(Deserialize)]
+struct TodoDescription {
+    description: String
+}
+
+#[derive(Clone, Copy)]
+pub enum TodoChange {
+    Add,
+    Complete,
+    Reopen,
+    Reorder,
+}
+
+pub async fn get_todo_entries(storage: &dyn Storage, conditions: &ConditionalRequest) -> Result<Response, Error> {
+    let cached_data = get_object_as_string_if_modified(storage, "todo.txt", conditions).await?;
+    cached_data_response(cached_data, get_default_headers(), |data| todos_as_json(&data))
+}
+
+/// Applies a change from the request body to todo.txt and returns the todos.
+/// Add, complete and reopen take `{"description": "..."}`, reorder takes the open todos as a list in their new order.
+pub async fn change_todo_entries(storage: &dyn Storage, change: TodoChange, if_match: Option<String>, body: Option<String>) -> Result<Response, Error> {
+    let bad_request = |message: &str| Ok(Response { status_code: 400, headers: get_default_headers(), body: message.to_string()});
+    let body = body.unwrap_or_default();
+
+    let object = match editable_data_or_precondition_response(get_object_as_string_if_etag_matches(storage, "todo.txt", if_match).await?) {
+        Ok(object) => object,
+        Err(response) => return Ok(response)
+    };
+    let data = object.data;
 
-    Ok(match cached_data {
-        BrowserCachedData::InCache => {
-            Response { status_code: 304, headers, body: "".to_string()}
+    let changed = match change {
+        TodoChange::Reorder => {
+            let order: Vec<String> = match serde_json::from_str(&body) {
+                Ok(order) => order,
+                Err(_) => return bad_request("Expected a list of todos")
+            };
+            reorder_todos(&data, &order)
         },
-        BrowserCachedData::NotInCache { data, etag } => {
-            headers.insert(Header::ETag, etag);
-            let todos: Vec<Todo> = parse_todo_file(&data).iter().map(|todo| Todo { description: todo.clone(), done: true }).collect();
-            Response { status_code: 200, headers, body: serde_json::to_string(&todos)?}
+        _ => {
+            let description = match serde_json::from_str::<TodoDescription>(&body) {
+                Ok(todo) if is_valid_description(&todo.description) => todo.description.trim().to_string(),
+                _ => return bad_request("Expected a todo with a description on a single line")
+            };
+
+            match change {
+                TodoChange::Add => Some(add_todo(&data, &description)),
+                TodoChange::Complete => {
+                    let today = Utc::now().with_timezone(&get_home_time_zone()?).naive_local().date();
+                    let completed = YearMonthDate { year: today.year() as u32, month: num_to_month(today.month()).unwrap(), date: today.day() };
+                    complete_todo(&data, &description, Some(completed))
+                },
+                _ => reopen_todo(&data, &description),
+            }
         }
-    })
+    };
+
+    let changed = match changed {
+        Some(changed) => changed,
+        None => return Ok(Response { status_code: 404, headers: get_default_headers(), body: "Todo not found".to_string()})
+    };
+
+    let body = todos_as_json(&changed)?;
+    let etag = match storage.put_if_match("todo.txt", changed, &object.etag).await? {
+        Some(etag) => etag,
+        None => return Ok(precondition_failed_response())
+    };
+    let mut headers = get_default_headers();
+    headers.insert(Header::ETag, etag);
+    Ok(Response { status_code: 200, headers, body })
+}
+
+fn todos_as_json(data: &str) -> Result<String, Error> {
+    Ok(serde_json::to_string(&parse_todo_file(data))?)
 }
 
-pub fn parse_todo_file(file: &str) -> Vec<String> {
+fn is_valid_description(description: &str) -> bool {
+    let description = description.trim();
+    !description.is_empty() && !description.contains('\n') && !description.contains('\r') && description != DONE_MARKER
+}
+
+/// Reads the open todos, and the done ones below `--- Ferdig ---`. A done todo may end with
+/// when it was finished, like `Buy milk {ferdig 12.5.2021}`.
+pub fn parse_todo_file(file: &str) -> Vec<Todo> {
+    let mut done = false;
+
     file.split("\n")
     .filter_map(|line| {
-        let description = line.trim();
-        
-        if description.len() > 0 {
-            Some(description.to_string())
+        let line = line.trim();
+
+        if line == DONE_MARKER {
+            done = true;
+            None
+        } else if line.len() > 0 {
+            let (description, completed) = if done { completed_regex(line) } else { (line, None) };
+            Some(Todo { description: description.to_string(), done, completed })
         } else {
             None
         }
     })
-    .take_while(|line| line != "--- Ferdig ---")
     .collect()
 }
 
+fn completed_regex(line: &str) -> (&str, Option<YearMonthDate>) {
+    lazy_static! {
+        static ref COMPLETED: Regex = Regex::new(r"(?x)
+        ^
+        (?P<description>.*?)
+        \s*
+        \{ferdig\s+(?P<date>\d{1,2})\.(?P<month>\d{1,2})\.(?P<year>\d{4})\}
+        $
+").unwrap();
+    }
+
+    let captures = match COMPLETED.captures(line) {
+        Some(captures) => captures,
+        None => return (line, None)
+    };
+
+    let completed = num_to_month(captures["month"].parse().unwrap()).map(|month| YearMonthDate {
+        year: captures["year"].parse().unwrap(),
+        month,
+        date: captures["date"].parse().unwrap()
+    });
+
+    (captures.name("description").unwrap().as_str(), completed)
+}
+
+/// Adds a todo after the last open one. Every other line is left as it was.
+pub fn add_todo(file: &str, description: &str) -> String {
+    let mut lines: Vec<&str> = file.split('\n').collect();
+    let marker = done_marker_line(&lines).unwrap_or(lines.len());
+    let index = (0..marker).rev().find(|index| !lines[*index].trim().is_empty()).map_or(0, |index| index + 1);
+
+    lines.insert(index, description);
+    lines.join("\n")
+}
+
+/// Moves an open todo to the top of the done list below `--- Ferdig ---`, adding the marker if there isn't one.
+pub fn complete_todo(file: &str, description: &str, completed: Option<YearMonthDate>) -> Option<String> {
+    let mut lines: Vec<&str> = file.split('\n').collect();
+    let marker = done_marker_line(&lines).unwrap_or(lines.len());
+    let index = (0..marker).find(|index| lines[*index].trim() == description)?;
+    lines.remove(index);
+
+    let done_line = match completed {
+        Some(completed) => format!("{} {{ferdig {}.{}.{}}}", description, completed.date, month_to_num(completed.month), completed.year),
+        None => description.to_string()
+    };
+
+    match done_marker_line(&lines) {
+        Some(marker) => lines.insert(marker + 1, &done_line),
+        None => {
+            // keep the file's trailing newline after the new done list.
+            let end = if lines.last() == Some(&"") { lines.len() - 1 } else { lines.len() };
+            lines.splice(end..end, vec!["", DONE_MARKER, &done_line]);
+        }
+    }
+
+    Some(lines.join("\n"))
+}
+
+/// Moves a done todo back to the end of the open list.
+pub fn reopen_todo(file: &str, description: &str) -> Option<String> {
+    let mut lines: Vec<&str> = file.split('\n').collect();
+    let marker = done_marker_line(&lines)?;
+    let index = (marker + 1..lines.len()).find(|index| completed_regex(lines[*index].trim()).0 == description)?;
+    lines.remove(index);
+
+    Some(add_todo(&lines.join("\n"), description))
+}
+
+/// Puts the open todos in the given order, which has to hold exactly the open todos.
+/// Blank lines stay where they were, and each todo keeps its line as it was written.
+pub fn reorder_todos(file: &str, order: &[String]) -> Option<String> {
+    let mut lines: Vec<&str> = file.split('\n').collect();
+    let marker = done_marker_line(&lines).unwrap_or(lines.len());
+    let slots: Vec<usize> = (0..marker).filter(|index| !lines[*index].trim().is_empty()).collect();
+    let mut open: Vec<&str> = slots.iter().map(|index| lines[*index]).collect();
+
+    if order.len() != open.len() {
+        return None;
+    }
+
+    let mut reordered = vec![];
+    for description in order {
+        let position = open.iter().position(|line| line.trim() == description.trim())?;
+        reordered.push(open.remove(position));
+    }
+
+    for (slot, line) in slots.into_iter().zip(reordered) {
+        lines[slot] = line;
+    }
+
+    Some(lines.join("\n"))
+}
+
+fn done_marker_line(lines: &[&str]) -> Option<usize> {
+    lines.iter().position(|line| line.trim() == DONE_MARKER)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::MemoryStorage;
 
     #[test]
     fn todo_parsing_test() {
-        let todos = parse_todo_file("Do A\n  Do B\n\n   \n Do C  \n\n--- Ferdig ---\nDo D\n");
-        assert_eq!(todos[0], "Do A");
-        assert_eq!(todos[1], "Do B");
-        assert_eq!(todos[2], "Do C");
+        let todos = parse_todo_file("Do A\n  Do B\n\n   \n Do C  \n\n--- Ferdig ---\nDo D\nDo E {ferdig 12.5.2021}\n");
+        assert_eq!(todos[0], Todo { description: "Do A".to_string(), done: false, completed: None });
+        assert_eq!(todos[1].description, "Do B");
+        assert_eq!(todos[2].description, "Do C");
+        assert_eq!(todos[3], Todo { description: "Do D".to_string(), done: true, completed: None });
+        assert_eq!(todos[4], Todo {
+            description: "Do E".to_string(),
+            done: true,
+            completed: Some(YearMonthDate { year: 2021, month: cal_rem_shared::Month::May, date: 12 })
+        });
+        assert_eq!(5, todos.len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn todo_change_test() {
+        let file = "Do A\n  Do B\n\n--- Ferdig ---\nDo D\n";
+
+        assert_eq!("Do A\n  Do B\nDo C\n\n--- Ferdig ---\nDo D\n", add_todo(file, "Do C"));
+        assert_eq!("Do C\n", add_todo("", "Do C"));
+
+        assert_eq!(Some("  Do B\n\n--- Ferdig ---\nDo A\nDo D\n".to_string()), complete_todo(file, "Do A", None));
+        let completed = YearMonthDate { year: 2021, month: cal_rem_shared::Month::May, date: 12 };
+        assert_eq!(Some("Do B\n\n--- Ferdig ---\nDo A {ferdig 12.5.2021}\n".to_string()), complete_todo("Do A\nDo B\n", "Do A", Some(completed)));
+        assert_eq!(None, complete_todo(file, "Do D", None));
+
+        assert_eq!(Some("Do A\n  Do B\nDo D\n\n--- Ferdig ---\n".to_string()), reopen_todo(file, "Do D"));
+        assert_eq!(Some("Do B\nDo A\n\n--- Ferdig ---\n".to_string()), reopen_todo("Do B\n\n--- Ferdig ---\nDo A {ferdig 12.5.2021}\n", "Do A"));
+        assert_eq!(None, reopen_todo(file, "Do A"));
+
+        assert_eq!(Some("  Do B\nDo A\n\n--- Ferdig ---\nDo D\n".to_string()), reorder_todos(file, &["Do B".to_string(), "Do A".to_string()]));
+        assert_eq!(None, reorder_todos(file, &["Do B".to_string()]));
+        assert_eq!(None, reorder_todos(file, &["Do B".to_string(), "Do D".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn change_todo_entries_test() {
+        let storage = MemoryStorage::default().with_object("todo.txt", "Vaske\n");
+        let etag = storage.get("todo.txt").await.unwrap().etag;
+        let add = |if_match: Option<String>| change_todo_entries(&storage, TodoChange::Add, if_match, Some(r#"{"description": "Rydde"}"#.to_string()));
+
+        assert_eq!(428, add(None).await.unwrap().status_code);
+        let response = add(Some(etag.clone())).await.unwrap();
+        assert_eq!(200, response.status_code);
+        assert_eq!(Some("Vaske\nRydde\n".to_string()), storage.object("todo.txt"));
+        assert_eq!(412, add(Some(etag)).await.unwrap().status_code);
+
+        let etag = response.headers.get(&Header::ETag).cloned();
+        let response = change_todo_entries(&storage, TodoChange::Reopen, etag, Some(r#"{"description": "Rydde"}"#.to_string())).await.unwrap();
+        assert_eq!(404, response.status_code);
+    }
+}
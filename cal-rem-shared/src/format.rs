@@ -0,0 +1,214 @@
+use crate::{Entry, Frequency, Month, Recurrence, RecurrenceLimit, Reminder, month_to_num};
+
+/// Writes entries back in the calendar.txt format, with a year and month header whenever
+/// the year or month changes. Entries are expected to be in calendar order.
+pub fn format_calendar_file(entries: &[Entry]) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut year: Option<u32> = None;
+    let mut month: Option<Month> = None;
+
+    for entry in entries {
+        if year != Some(entry.year) {
+            if year.is_some() {
+                lines.push("".to_string());
+            }
+            lines.push(entry.year.to_string());
+            year = Some(entry.year);
+            month = None;
+        }
+
+        if month != Some(entry.month) {
+            if month.is_some() {
+                lines.push("".to_string());
+            }
+            lines.push(month_name(entry.month).to_string());
+            month = Some(entry.month);
+        }
+
+        lines.push(format_entry(entry));
+    }
+
+    lines.push("".to_string());
+    lines.join("\n")
+}
+
+/// Writes a single entry as it appears under its month header, e.g. `5-11?. Trip #ferie @ Rome [11.00] {årlig}`.
+pub fn format_entry(entry: &Entry) -> String {
+    let mut parts = vec![format!("{}.", format_date(entry))];
+
+    if !entry.description.is_empty() {
+        parts.push(entry.description.clone());
+    }
+
+    parts.extend(entry.tags.iter().map(|tag| format!("#{}", tag)));
+
+    if let Some(location) = &entry.location {
+        parts.push(format!("@ {}", location));
+    }
+
+    match (entry.start_time, entry.end_time) {
+        (Some(start), Some(end)) => parts.push(format!("[{:02}.{:02}-{:02}.{:02}]", start.hour, start.minute, end.hour, end.minute)),
+        (Some(start), None) => parts.push(format!("[{:02}.{:02}]", start.hour, start.minute)),
+        _ => {}
+    }
+
+    if let Some(time_zone) = &entry.time_zone {
+        parts.push(format!("{{tidssone {}}}", time_zone));
+    }
+
+    if let Some(recurrence) = &entry.recurrence {
+        parts.push(format!("{{{}}}", format_recurrence(recurrence)));
+    }
+
+    if let Some(reminders) = &entry.reminders {
+        parts.push(format!("{{{}}}", format_reminders(reminders)));
+    }
+
+    // ids the parser would derive again are left out, everything else is kept explicitly.
+    if !entry.id.is_empty() && entry.id != entry.content_id() {
+        parts.push(format!("{{id {}}}", entry.id));
+    }
+
+    parts.join(" ")
+}
+
+pub fn month_name(month: Month) -> &'static str {
+    match month {
+        Month::January => "Januar",
+        Month::February => "Februar",
+        Month::March => "Mars",
+        Month::April => "April",
+        Month::May => "Mai",
+        Month::June => "Juni",
+        Month::July => "Juli",
+        Month::August => "August",
+        Month::September => "September",
+        Month::October => "Oktober",
+        Month::November => "November",
+        Month::December => "Desember",
+    }
+}
+
+fn format_date(entry: &Entry) -> String {
+    let uncertain = if entry.uncertain_date { "?" } else { "" };
+
+    match (entry.start_date, entry.end_date) {
+        (None, _) => "?".to_string(),
+        (Some(start), None) => format!("{}{}", start, uncertain),
+        (Some(start), Some(end)) => {
+            let end_month = entry.end_month.unwrap_or(entry.month);
+            let end_year = entry.end_year.unwrap_or(entry.year);
+            // the parser assumes a range into an earlier month ends the following year.
+            let implied_end_year = if end_month < entry.month { entry.year + 1 } else { entry.year };
+
+            if end_year != implied_end_year {
+                format!("{}-{}.{}.{}{}", start, end, month_to_num(end_month), end_year, uncertain)
+            } else if end_month != entry.month || end_year != entry.year {
+                format!("{}-{}.{}{}", start, end, month_to_num(end_month), uncertain)
+            } else {
+                format!("{}-{}{}", start, end, uncertain)
+            }
+        }
+    }
+}
+
+fn format_recurrence(recurrence: &Recurrence) -> String {
+    let frequency = match recurrence.frequency {
+        Frequency::Weekly { interval: 1 } => "ukentlig".to_string(),
+        Frequency::Weekly { interval } => format!("hver {}. uke", interval),
+        Frequency::Monthly => "månedlig".to_string(),
+        Frequency::Yearly => "årlig".to_string(),
+    };
+
+    match recurrence.limit {
+        Some(RecurrenceLimit::Until(until)) => format!("{} til {}.{}.{}", frequency, until.date, month_to_num(until.month), until.year),
+        Some(RecurrenceLimit::Count(count)) => format!("{} {} ganger", frequency, count),
+        None => frequency
+    }
+}
+
+fn format_reminders(reminders: &[Reminder]) -> String {
+    if reminders.is_empty() {
+        return "ingen varsel".to_string();
+    }
+
+    let offsets: Vec<String> = reminders.iter().map(|reminder| {
+        let minutes = reminder.minutes_before;
+        let units = [(60 * 24 * 7, "u"), (60 * 24, "d"), (60, "t")];
+
+        units.iter()
+            .find(|(minutes_in_unit, _)| minutes > 0 && minutes % minutes_in_unit == 0)
+            .map_or(format!("{}m", minutes), |(minutes_in_unit, unit)| format!("{}{}", minutes / minutes_in_unit, unit))
+    }).collect();
+
+    format!("varsel {}", offsets.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HourMinute, YearMonthDate};
+
+    #[test]
+    fn entry_formatting_test() {
+        let mut event = Entry::on(2020, Month::May, Some(10), "Event");
+        event.location = Some("Place A".to_string());
+        event.start_time = Some(HourMinute { hour: 8, minute: 30 });
+        event.end_time = Some(HourMinute { hour: 20, minute: 0 });
+        event.tags = vec!["jobb".to_string()];
+        assert_eq!("10. Event #jobb @ Place A [08.30-20.00]", format_entry(&event));
+
+        event.time_zone = Some("Europe/London".to_string());
+        assert_eq!("10. Event #jobb @ Place A [08.30-20.00] {tidssone Europe/London}", format_entry(&event));
+
+        let mut event = Entry::on(2020, Month::May, Some(24), "Uncertain");
+        event.uncertain_date = true;
+        event.reminders = Some(vec![Reminder { minutes_before: 60 * 24 * 7 }, Reminder { minutes_before: 90 }]);
+        assert_eq!("24?. Uncertain {varsel 1u, 90m}", format_entry(&event));
+
+        let event = Entry::on(2020, Month::October, None, "Unknown date");
+        assert_eq!("?. Unknown date", format_entry(&event));
+
+        let mut event = Entry::on(2020, Month::July, Some(5), "Range");
+        event.end_date = Some(11);
+        event.end_month = Some(Month::July);
+        event.end_year = Some(2020);
+        event.recurrence = Some(Recurrence { frequency: Frequency::Weekly { interval: 2 }, limit: Some(RecurrenceLimit::Count(4)) });
+        assert_eq!("5-11. Range {hver 2. uke 4 ganger}", format_entry(&event));
+
+        let mut event = Entry::on(2020, Month::December, Some(28), "Into next year");
+        event.end_date = Some(3);
+        event.end_month = Some(Month::January);
+        event.end_year = Some(2021);
+        event.reminders = Some(vec![]);
+        assert_eq!("28-3.1. Into next year {ingen varsel}", format_entry(&event));
+
+        event.id = event.content_id();
+        assert_eq!("28-3.1. Into next year {ingen varsel}", format_entry(&event));
+
+        event.id = "new-year".to_string();
+        assert_eq!("28-3.1. Into next year {ingen varsel} {id new-year}", format_entry(&event));
+
+        let mut event = Entry::on(2020, Month::May, Some(5), "A year and a bit");
+        event.end_date = Some(7);
+        event.end_month = Some(Month::May);
+        event.end_year = Some(2021);
+        event.uncertain_date = true;
+        event.recurrence = Some(Recurrence {
+            frequency: Frequency::Yearly,
+            limit: Some(RecurrenceLimit::Until(YearMonthDate { year: 2030, month: Month::May, date: 1 }))
+        });
+        assert_eq!("5-7.5.2021?. A year and a bit {årlig til 1.5.2030}", format_entry(&event));
+    }
+
+    #[test]
+    fn calendar_formatting_test() {
+        let entries = vec![
+            Entry::on(2020, Month::November, Some(1), "A"),
+            Entry::on(2020, Month::December, Some(2), "B"),
+            Entry::on(2020, Month::December, Some(3), "C"),
+            Entry::on(2021, Month::December, Some(4), "D"),
+        ];
+        assert_eq!("2020\nNovember\n1. A\n\nDesember\n2. B\n3. C\n\n2021\nDesember\n4. D\n", format_calendar_file(&entries));
+    }
+}
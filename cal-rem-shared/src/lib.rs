@@ -1,6 +1,14 @@
 use serde::{Deserialize, Serialize};
 use chrono::prelude::*;
+use chrono::{Duration, LocalResult};
 use chrono_tz::{Tz, Europe::Oslo};
+use std::fmt;
+
+mod format;
+mod recurrence;
+
+pub use format::{format_calendar_file, format_entry, month_name};
+pub use recurrence::{Frequency, Recurrence, RecurrenceLimit, YearMonthDate, expand_recurring_entries};
 
 #[derive(Serialize, Deserialize)]
 pub enum Command {
@@ -14,6 +22,19 @@ pub struct RequestBody {
     pub parameters: String,
 }
 
+/// Configuration the lambda serves to the frontend.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Settings {
+    pub time_zone: String,
+}
+
+impl Settings {
+    /// The calendar's home time zone, falling back to Europe/Oslo if it isn't a known zone.
+    pub fn home_time_zone(&self) -> Tz {
+        self.time_zone.parse().unwrap_or(Oslo)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
 pub struct HourMinute {
     pub hour: u32,
@@ -21,6 +42,11 @@ pub struct HourMinute {
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub struct Reminder {
+    pub minutes_before: u32
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
 pub enum Month {
     January,
     February,
@@ -38,39 +64,182 @@ pub enum Month {
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Entry {
+    // see content_id, unless the line gives one with {id ...}.
+    #[serde(default)]
+    pub id: String,
+    // on the occurrences of a recurring entry, the id of the recurring entry, which writes go to.
+    #[serde(default)]
+    pub series_id: Option<String>,
     pub description: String,
     pub location: Option<String>,
     pub year: u32,
     pub month: Month,
     pub start_date: Option<u32>,
     pub end_date: Option<u32>,
+    pub end_month: Option<Month>,
+    pub end_year: Option<u32>,
+    #[serde(default)]
+    pub uncertain_date: bool,
     pub start_time: Option<HourMinute>,
-    pub end_time: Option<HourMinute>
+    pub end_time: Option<HourMinute>,
+    // an IANA zone name for entries whose times aren't in the calendar's home time zone.
+    pub time_zone: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+    // None gives the default reminders, while an empty list turns them off.
+    pub reminders: Option<Vec<Reminder>>
 }
 
 impl Entry {
+    /// An entry with only a date and a description, for setting the other fields on.
+    pub fn on(year: u32, month: Month, start_date: Option<u32>, description: &str) -> Entry {
+        Entry {
+            id: "".to_string(),
+            series_id: None,
+            description: description.to_string(),
+            location: None,
+            year,
+            month,
+            start_date,
+            end_date: None,
+            end_month: None,
+            end_year: None,
+            uncertain_date: false,
+            start_time: None,
+            end_time: None,
+            time_zone: None,
+            tags: vec![],
+            recurrence: None,
+            reminders: None
+        }
+    }
+
     pub fn create_message(&self) -> String {
         format!("{:?}{}{}: {}",
             self.month,
-            self.start_date.map_or(" no date".to_string(), |date| format!(" {}.", &date.to_string())),
-            self.start_time.map_or("".to_string(), |time| format!(", {:02}.{:02}", time.hour, time.minute)),
+            self.date_description(),
+            self.start_time.map_or("".to_string(), |time| format!(", {:02}.{:02}{}", time.hour, time.minute,
+                self.time_zone.as_ref().map_or("".to_string(), |time_zone| format!(" {}", time_zone)))),
             self.description)
     }
 
-    pub fn get_oslo_date_time(&self) -> DateTime<Tz> {
-        Oslo.ymd(
-            self.year as i32, 
-            month_to_num(self.month), 
-            self.start_date.unwrap_or(1)
-        ).and_hms(
-            self.start_time.map_or(8, |hm| hm.hour),
-            self.start_time.map_or(0, |hm| hm.minute),
-            0
+    fn date_description(&self) -> String {
+        let uncertain = if self.uncertain_date { "?" } else { "" };
+
+        match (self.start_date, self.end_date) {
+            (None, _) => " no date".to_string(),
+            (Some(start), None) => format!(" {}{}.", start, uncertain),
+            (Some(start), Some(end)) => {
+                let end_month = self.end_month.unwrap_or(self.month);
+                if end_month == self.month && self.end_year.unwrap_or(self.year) == self.year {
+                    format!(" {}.-{}{}.", start, end, uncertain)
+                } else {
+                    format!(" {}.-{:?} {}{}.", start, end_month, end, uncertain)
+                }
+            }
+        }
+    }
+
+    pub fn start_naive_date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year as i32, month_to_num(self.month), self.start_date?)
+    }
+
+    /// The last day of a date range, which may be in a later month or year than the start.
+    pub fn end_naive_date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(
+            self.end_year.unwrap_or(self.year) as i32,
+            month_to_num(self.end_month.unwrap_or(self.month)),
+            self.end_date?
         )
     }
+
+    /// An id derived from the entry's date and description, so it doesn't change when other
+    /// lines in calendar.txt are edited, or when the entry's time, location or tags are.
+    pub fn content_id(&self) -> String {
+        let key = format!("{}-{}-{}-{}",
+            self.year,
+            month_to_num(self.month),
+            self.start_date.map_or("?".to_string(), |date| date.to_string()),
+            self.description);
+
+        format!("{:016x}", fnv1a(key.as_bytes()))
+    }
+
+    /// The zone the entry's times are written in: its own if it has one, otherwise `home_time_zone`.
+    pub fn time_zone(&self, home_time_zone: Tz) -> Tz {
+        self.time_zone.as_ref().and_then(|time_zone| time_zone.parse().ok()).unwrap_or(home_time_zone)
+    }
+
+    /// When the entry starts, at 08.00 if it has no time and on the 1st if it has no date.
+    /// Fails instead of panicking on dates and times that don't exist, like 31. in a 30 day month.
+    pub fn get_date_time(&self, home_time_zone: Tz) -> Result<DateTime<Tz>, DateTimeError> {
+        let date = self.start_naive_date_or_first()?;
+        local_date_time(self.time_zone(home_time_zone), date, self.start_time.unwrap_or(HourMinute { hour: 8, minute: 0 }))
+    }
+
+    /// When the entry ends: the end time on the last day of a range, or 23.59 if it has no end time.
+    pub fn get_end_date_time(&self, home_time_zone: Tz) -> Result<DateTime<Tz>, DateTimeError> {
+        if self.end_date.is_none() {
+            return self.get_date_time(home_time_zone);
+        }
+
+        let end = self.end_naive_date().ok_or(DateTimeError::InvalidDate {
+            year: self.end_year.unwrap_or(self.year),
+            month: self.end_month.unwrap_or(self.month),
+            date: self.end_date.unwrap()
+        })?;
+        local_date_time(self.time_zone(home_time_zone), end, self.end_time.unwrap_or(HourMinute { hour: 23, minute: 59 }))
+    }
+
+    fn start_naive_date_or_first(&self) -> Result<NaiveDate, DateTimeError> {
+        let date = self.start_date.unwrap_or(1);
+        NaiveDate::from_ymd_opt(self.year as i32, month_to_num(self.month), date)
+            .ok_or(DateTimeError::InvalidDate { year: self.year, month: self.month, date })
+    }
+}
+
+/// FNV-1a, for hashes that have to be the same across builds, like ids and ETags, which std's
+/// hashers don't promise.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DateTimeError {
+    InvalidDate { year: u32, month: Month, date: u32 },
+    InvalidTime(HourMinute),
+}
+
+impl fmt::Display for DateTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DateTimeError::InvalidDate { year, month, date } => write!(f, "{:?} {} has no date {}", month, year, date),
+            DateTimeError::InvalidTime(time) => write!(f, "{:02}.{:02} is not a valid time of day", time.hour, time.minute),
+        }
+    }
 }
 
-fn month_to_num(month: Month) -> u32 {
+impl std::error::Error for DateTimeError {}
+
+/// Places a wall clock time in a time zone, with a fixed policy for daylight saving changes:
+/// a time skipped when the clocks go forward is moved forward by the length of the gap
+/// (02.30 becomes 03.30), and a time that happens twice when the clocks go back is the first one.
+pub fn local_date_time(time_zone: Tz, date: NaiveDate, time: HourMinute) -> Result<DateTime<Tz>, DateTimeError> {
+    let local = date.and_hms_opt(time.hour, time.minute, 0).ok_or(DateTimeError::InvalidTime(time))?;
+
+    Ok(match time_zone.from_local_datetime(&local) {
+        LocalResult::Single(date_time) => date_time,
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => {
+            // read the skipped time with the offset from before the gap, which lands after it.
+            let offset_before_gap = time_zone.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+            time_zone.from_utc_datetime(&(local - Duration::seconds(offset_before_gap.local_minus_utc() as i64)))
+        }
+    })
+}
+
+pub fn month_to_num(month: Month) -> u32 {
     match month {
         Month::January => 1,
         Month::February => 2,
@@ -87,8 +256,81 @@ fn month_to_num(month: Month) -> u32 {
     }
 }
 
+pub fn num_to_month(num: u32) -> Option<Month> {
+    match num {
+        1 => Some(Month::January),
+        2 => Some(Month::February),
+        3 => Some(Month::March),
+        4 => Some(Month::April),
+        5 => Some(Month::May),
+        6 => Some(Month::June),
+        7 => Some(Month::July),
+        8 => Some(Month::August),
+        9 => Some(Month::September),
+        10 => Some(Month::October),
+        11 => Some(Month::November),
+        12 => Some(Month::December),
+        _ => None
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Todo {
     pub description: String,
-    pub done: bool
-}
\ No newline at end of file
+    pub done: bool,
+    // when a done todo was finished, if todo.txt says so.
+    #[serde(default)]
+    pub completed: Option<YearMonthDate>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(year: u32, month: Month, date: u32, time: HourMinute) -> Entry {
+        Entry { start_time: Some(time), ..Entry::on(year, month, Some(date), "Event") }
+    }
+
+    #[test]
+    fn daylight_saving_test() {
+        // The clocks go from 02.00 to 03.00, so 02.30 is moved to 03.30
+        let spring = entry(2021, Month::March, 28, HourMinute { hour: 2, minute: 30 }).get_date_time(Oslo).unwrap();
+        assert_eq!("2021-03-28T03:30:00+02:00", spring.to_rfc3339());
+
+        // The clocks go from 03.00 back to 02.00, so 02.30 happens twice and the first is used
+        let autumn = entry(2021, Month::October, 31, HourMinute { hour: 2, minute: 30 }).get_date_time(Oslo).unwrap();
+        assert_eq!("2021-10-31T02:30:00+02:00", autumn.to_rfc3339());
+
+        let normal = entry(2021, Month::October, 31, HourMinute { hour: 10, minute: 0 }).get_date_time(Oslo).unwrap();
+        assert_eq!("2021-10-31T10:00:00+01:00", normal.to_rfc3339());
+    }
+
+    #[test]
+    fn content_id_test() {
+        let event = entry(2021, Month::June, 15, HourMinute { hour: 10, minute: 0 });
+        assert_eq!("bc0d4d209369c753", event.content_id());
+
+        let mut moved = event.clone();
+        moved.start_time = Some(HourMinute { hour: 12, minute: 0 });
+        moved.location = Some("Elsewhere".to_string());
+        assert_eq!(event.content_id(), moved.content_id());
+
+        let mut renamed = event.clone();
+        renamed.description = "Another event".to_string();
+        assert_ne!(event.content_id(), renamed.content_id());
+    }
+
+    #[test]
+    fn invalid_date_time_test() {
+        let result = entry(2021, Month::June, 31, HourMinute { hour: 10, minute: 0 }).get_date_time(Oslo);
+        assert_eq!(Err(DateTimeError::InvalidDate { year: 2021, month: Month::June, date: 31 }), result);
+
+        let result = entry(2021, Month::June, 30, HourMinute { hour: 24, minute: 0 }).get_date_time(Oslo);
+        assert_eq!(Err(DateTimeError::InvalidTime(HourMinute { hour: 24, minute: 0 })), result);
+
+        let mut range = entry(2021, Month::June, 28, HourMinute { hour: 10, minute: 0 });
+        range.end_date = Some(31);
+        assert!(range.get_date_time(Oslo).is_ok());
+        assert_eq!(Err(DateTimeError::InvalidDate { year: 2021, month: Month::June, date: 31 }), range.get_end_date_time(Oslo));
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use chrono::prelude::*;
+use chrono::Duration;
+use crate::{Entry, Month, month_to_num, num_to_month};
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub enum Frequency {
+    Weekly { interval: u32 },
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub struct YearMonthDate {
+    pub year: u32,
+    pub month: Month,
+    pub date: u32
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub enum RecurrenceLimit {
+    Until(YearMonthDate),
+    Count(u32),
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub limit: Option<RecurrenceLimit>
+}
+
+impl YearMonthDate {
+    pub fn to_naive_date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year as i32, month_to_num(self.month), self.date)
+    }
+}
+
+impl Entry {
+    /// Returns the concrete occurrences of a recurring entry that start within `from..=to`.
+    /// Entries without a recurrence (or without a known date) are returned as they are.
+    pub fn occurrences_between(&self, from: NaiveDate, to: NaiveDate) -> Vec<Entry> {
+        let (recurrence, start) = match (self.recurrence, self.start_naive_date()) {
+            (Some(recurrence), Some(start)) => (recurrence, start),
+            _ => return vec![self.clone()]
+        };
+
+        let until = match recurrence.limit {
+            Some(RecurrenceLimit::Until(until)) => until.to_naive_date(),
+            _ => None
+        };
+        let count = match recurrence.limit {
+            Some(RecurrenceLimit::Count(count)) => Some(count),
+            _ => None
+        };
+
+        let mut occurrences = vec![];
+        let mut found = 0;
+
+        for n in 0.. {
+            let (anchor, date) = nth_occurrence(start, recurrence.frequency, n);
+
+            if anchor > to || until.map_or(false, |until| anchor > until) || count.map_or(false, |count| found >= count) {
+                break;
+            }
+
+            // Monthly and yearly recurrences skip months that don't have the date (e.g. the 31st).
+            let date = match date {
+                Some(date) => date,
+                None => continue
+            };
+
+            if date > to || until.map_or(false, |until| date > until) {
+                break;
+            }
+
+            found += 1;
+
+            if date >= from {
+                occurrences.push(self.occurrence_on(date));
+            }
+        }
+
+        occurrences
+    }
+
+    fn occurrence_on(&self, date: NaiveDate) -> Entry {
+        let mut entry = self.clone();
+        entry.year = date.year() as u32;
+        entry.month = num_to_month(date.month()).unwrap();
+        entry.start_date = Some(date.day());
+        entry.recurrence = None;
+        // occurrences share the series' line, but each needs its own id.
+        if !self.id.is_empty() {
+            entry.id = format!("{}-{}", self.id, date.format("%Y%m%d"));
+            entry.series_id = Some(self.id.clone());
+        }
+
+        if let (Some(start), Some(end)) = (self.start_naive_date(), self.end_naive_date()) {
+            let end = date + (end - start);
+            entry.end_date = Some(end.day());
+            entry.end_month = num_to_month(end.month());
+            entry.end_year = Some(end.year() as u32);
+        }
+
+        entry
+    }
+}
+
+/// Expands every recurring entry into its occurrences within `from..=to`, keeping the
+/// one-off entries untouched. The result is sorted by date so month headers stay in order.
+pub fn expand_recurring_entries(entries: &[Entry], from: NaiveDate, to: NaiveDate) -> Vec<Entry> {
+    let mut expanded: Vec<Entry> = entries.iter().flat_map(|entry| entry.occurrences_between(from, to)).collect();
+
+    expanded.sort_by_key(|entry| (entry.year, entry.month, entry.start_date.unwrap_or(0)));
+    expanded
+}
+
+// Returns the first day the n-th occurrence could fall on, together with the occurrence itself
+// if that date exists.
+fn nth_occurrence(start: NaiveDate, frequency: Frequency, n: u32) -> (NaiveDate, Option<NaiveDate>) {
+    match frequency {
+        Frequency::Weekly { interval } => {
+            let date = start + Duration::weeks(interval.max(1) as i64 * n as i64);
+            (date, Some(date))
+        },
+        Frequency::Monthly => {
+            let months = start.month0() + n;
+            let year = start.year() + (months / 12) as i32;
+            let month = months % 12 + 1;
+            (NaiveDate::from_ymd_opt(year, month, 1).unwrap(), NaiveDate::from_ymd_opt(year, month, start.day()))
+        },
+        Frequency::Yearly => {
+            let year = start.year() + n as i32;
+            (NaiveDate::from_ymd_opt(year, start.month(), 1).unwrap(), NaiveDate::from_ymd_opt(year, start.month(), start.day()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(year: u32, month: Month, date: u32, recurrence: Recurrence) -> Entry {
+        Entry { recurrence: Some(recurrence), ..Entry::on(year, month, Some(date), "Event") }
+    }
+
+    fn dates(entries: &[Entry]) -> Vec<(u32, Month, u32)> {
+        entries.iter().map(|entry| (entry.year, entry.month, entry.start_date.unwrap())).collect()
+    }
+
+    #[test]
+    fn weekly_recurrence_test() {
+        let weekly = entry(2021, Month::May, 25, Recurrence { frequency: Frequency::Weekly { interval: 1 }, limit: None });
+        let occurrences = weekly.occurrences_between(NaiveDate::from_ymd_opt(2021, 6, 1).unwrap(), NaiveDate::from_ymd_opt(2021, 6, 15).unwrap());
+        assert_eq!(vec![(2021, Month::June, 1), (2021, Month::June, 8), (2021, Month::June, 15)], dates(&occurrences));
+        assert!(occurrences.iter().all(|occurrence| occurrence.recurrence.is_none()));
+
+        let mut weekly = weekly;
+        weekly.id = "training".to_string();
+        let occurrences = weekly.occurrences_between(NaiveDate::from_ymd_opt(2021, 6, 1).unwrap(), NaiveDate::from_ymd_opt(2021, 6, 8).unwrap());
+        assert_eq!(vec!["training-20210601", "training-20210608"], occurrences.iter().map(|occurrence| occurrence.id.as_str()).collect::<Vec<&str>>());
+        assert!(occurrences.iter().all(|occurrence| occurrence.series_id.as_deref() == Some("training")));
+
+        let fortnightly = entry(2021, Month::May, 25, Recurrence {
+            frequency: Frequency::Weekly { interval: 2 },
+            limit: Some(RecurrenceLimit::Until(YearMonthDate { year: 2021, month: Month::July, date: 5 }))
+        });
+        let occurrences = fortnightly.occurrences_between(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(), NaiveDate::from_ymd_opt(2021, 12, 31).unwrap());
+        assert_eq!(vec![(2021, Month::May, 25), (2021, Month::June, 8), (2021, Month::June, 22)], dates(&occurrences));
+    }
+
+    #[test]
+    fn monthly_and_yearly_recurrence_test() {
+        // Months without the 31st are skipped and don't count towards the limit.
+        let monthly = entry(2021, Month::January, 31, Recurrence { frequency: Frequency::Monthly, limit: Some(RecurrenceLimit::Count(3)) });
+        let occurrences = monthly.occurrences_between(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(), NaiveDate::from_ymd_opt(2022, 12, 31).unwrap());
+        assert_eq!(vec![(2021, Month::January, 31), (2021, Month::March, 31), (2021, Month::May, 31)], dates(&occurrences));
+
+        // Date ranges keep their length, even when an occurrence crosses into the next year.
+        let mut yearly_trip = entry(2020, Month::December, 28, Recurrence { frequency: Frequency::Yearly, limit: None });
+        yearly_trip.end_date = Some(3);
+        yearly_trip.end_month = Some(Month::January);
+        yearly_trip.end_year = Some(2021);
+        let occurrence = &yearly_trip.occurrences_between(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(), NaiveDate::from_ymd_opt(2021, 12, 31).unwrap())[0];
+        assert_eq!((2021, Month::December, 28), (occurrence.year, occurrence.month, occurrence.start_date.unwrap()));
+        assert_eq!((2022, Month::January, 3), (occurrence.end_year.unwrap(), occurrence.end_month.unwrap(), occurrence.end_date.unwrap()));
+
+        let yearly = entry(2020, Month::February, 29, Recurrence { frequency: Frequency::Yearly, limit: None });
+        let occurrences = yearly.occurrences_between(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(), NaiveDate::from_ymd_opt(2028, 12, 31).unwrap());
+        assert_eq!(vec![(2024, Month::February, 29), (2028, Month::February, 29)], dates(&occurrences));
+    }
+
+    #[test]
+    fn expansion_keeps_one_off_entries_and_sorts_test() {
+        let mut one_off = entry(2021, Month::June, 10, Recurrence { frequency: Frequency::Monthly, limit: None });
+        one_off.recurrence = None;
+        let monthly = entry(2021, Month::May, 5, Recurrence { frequency: Frequency::Monthly, limit: None });
+
+        let expanded = expand_recurring_entries(&[one_off, monthly], NaiveDate::from_ymd_opt(2021, 5, 1).unwrap(), NaiveDate::from_ymd_opt(2021, 7, 1).unwrap());
+        assert_eq!(vec![(2021, Month::May, 5), (2021, Month::June, 5), (2021, Month::June, 10)], dates(&expanded));
+    }
+}